@@ -0,0 +1,843 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::lexer::LitKind;
+
+#[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Type {
+    Bool,
+    Float,
+    Int,
+    String,
+    /// Fallout 4 `Var`, which holds a value of any type.
+    Var,
+    Object(String),
+    Array(Box<Type>),
+    /// Fallout 4 name of a custom event, used by `RegisterForCustomEvent`.
+    CustomEventName,
+    /// Fallout 4 name of a built-in script event, used by `RegisterForRemoteEvent`.
+    ScriptEventName,
+}
+
+#[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Script {
+    pub header: ScriptHeader,
+    pub members: Vec<Member>,
+    pub span: (usize, usize),
+}
+
+#[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ScriptFlag {
+    BetaOnly,
+    Conditional,
+    Const,
+    DebugOnly,
+    Default,
+    Hidden,
+    Native,
+}
+
+#[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ScriptHeader {
+    pub name: String,
+    pub extends: Option<String>,
+    pub flags: Vec<ScriptFlag>,
+    pub doc: Option<String>,
+    pub span: (usize, usize),
+}
+
+#[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Member {
+    Import(Import),
+    CustomEvent(CustomEvent),
+    Property(Property),
+    Group(Group),
+    Function(Function),
+    Event(Event),
+    State(State),
+    Struct(Struct),
+    Var(VarDecl),
+}
+
+#[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Import {
+    pub name: String,
+    pub span: (usize, usize),
+}
+
+#[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CustomEvent {
+    pub name: String,
+    pub span: (usize, usize),
+}
+
+#[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum PropertyKind {
+    Full,
+    Auto,
+    AutoReadOnly,
+}
+
+#[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum PropertyFlag {
+    Conditional,
+    Const,
+    Hidden,
+    Mandatory,
+}
+
+#[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Property {
+    pub ty: Type,
+    pub name: String,
+    pub kind: PropertyKind,
+    pub flags: Vec<PropertyFlag>,
+    pub init: Option<Expr>,
+    pub getter: Option<Box<Function>>,
+    pub setter: Option<Box<Function>>,
+    pub doc: Option<String>,
+    pub span: (usize, usize),
+}
+
+#[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Group {
+    pub name: String,
+    pub flags: Vec<String>,
+    pub properties: Vec<Property>,
+    pub span: (usize, usize),
+}
+
+#[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum FnFlag {
+    BetaOnly,
+    DebugOnly,
+    Global,
+    Native,
+}
+
+#[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Param {
+    pub ty: Type,
+    pub name: String,
+    pub default: Option<Expr>,
+    pub span: (usize, usize),
+}
+
+#[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Function {
+    pub return_type: Option<Type>,
+    pub name: String,
+    pub params: Vec<Param>,
+    pub flags: Vec<FnFlag>,
+    pub body: Vec<Stmt>,
+    pub doc: Option<String>,
+    pub span: (usize, usize),
+}
+
+#[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Event {
+    pub name: String,
+    pub params: Vec<Param>,
+    pub is_native: bool,
+    pub body: Vec<Stmt>,
+    pub doc: Option<String>,
+    pub span: (usize, usize),
+}
+
+#[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum StateMember {
+    Function(Function),
+    Event(Event),
+}
+
+#[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct State {
+    pub name: String,
+    pub is_auto: bool,
+    pub members: Vec<StateMember>,
+    pub span: (usize, usize),
+}
+
+#[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum VarFlag {
+    Conditional,
+    Const,
+}
+
+#[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct VarDecl {
+    pub ty: Type,
+    pub name: String,
+    pub init: Option<Expr>,
+    pub flags: Vec<VarFlag>,
+    pub span: (usize, usize),
+}
+
+#[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Struct {
+    pub name: String,
+    pub fields: Vec<VarDecl>,
+    pub span: (usize, usize),
+}
+
+#[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum AssignOp {
+    Assign,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+#[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct IfArm {
+    pub cond: Expr,
+    pub body: Vec<Stmt>,
+    pub span: (usize, usize),
+}
+
+#[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Stmt {
+    Var(VarDecl),
+    Assign { target: Expr, op: AssignOp, value: Expr, span: (usize, usize) },
+    Return { value: Option<Expr>, span: (usize, usize) },
+    If { arms: Vec<IfArm>, else_block: Option<Vec<Stmt>>, span: (usize, usize) },
+    While { cond: Expr, body: Vec<Stmt>, span: (usize, usize) },
+    Expr(Expr),
+}
+
+impl Stmt {
+    pub fn span(&self) -> (usize, usize) {
+        match self {
+            Stmt::Var(decl) => decl.span,
+            Stmt::Expr(expr) => expr.span(),
+            Stmt::Assign { span, .. }
+            | Stmt::Return { span, .. }
+            | Stmt::If { span, .. }
+            | Stmt::While { span, .. } => *span,
+        }
+    }
+}
+
+#[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum UnOp {
+    Neg,
+    Not,
+}
+
+#[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum BinOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+/// A call argument, `name` is set for Fallout 4 named arguments such as `Foo(x = 1)`.
+#[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Arg {
+    pub name: Option<String>,
+    pub value: Expr,
+    pub span: (usize, usize),
+}
+
+#[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Expr {
+    Literal { lit: LitKind, span: (usize, usize) },
+    Ident { name: String, span: (usize, usize) },
+    Unary { op: UnOp, expr: Box<Expr>, span: (usize, usize) },
+    Binary { op: BinOp, lhs: Box<Expr>, rhs: Box<Expr>, span: (usize, usize) },
+    Call { callee: Box<Expr>, args: Vec<Arg>, span: (usize, usize) },
+    Member { base: Box<Expr>, field: String, span: (usize, usize) },
+    Index { base: Box<Expr>, index: Box<Expr>, span: (usize, usize) },
+    Cast { expr: Box<Expr>, ty: Type, span: (usize, usize) },
+    TypeCheck { expr: Box<Expr>, ty: Type, span: (usize, usize) },
+    Paren { expr: Box<Expr>, span: (usize, usize) },
+    NewArray { ty: Type, size: Box<Expr>, span: (usize, usize) },
+    NewStruct { name: String, span: (usize, usize) },
+    SelfRef { span: (usize, usize) },
+    ParentRef { span: (usize, usize) },
+}
+
+impl Expr {
+    pub fn span(&self) -> (usize, usize) {
+        match self {
+            Expr::Literal { span, .. }
+            | Expr::Ident { span, .. }
+            | Expr::Unary { span, .. }
+            | Expr::Binary { span, .. }
+            | Expr::Call { span, .. }
+            | Expr::Member { span, .. }
+            | Expr::Index { span, .. }
+            | Expr::Cast { span, .. }
+            | Expr::TypeCheck { span, .. }
+            | Expr::Paren { span, .. }
+            | Expr::NewArray { span, .. }
+            | Expr::NewStruct { span, .. }
+            | Expr::SelfRef { span }
+            | Expr::ParentRef { span } => *span,
+        }
+    }
+}
+
+/// Walks the AST, one method per node type.
+///
+/// Every method defaults to the matching `walk_*` function, which visits the node's children, so
+/// implementors only override the nodes they care about and call `walk_*` to keep descending.
+pub trait Visitor {
+    fn visit_script(&mut self, script: &Script) {
+        walk_script(self, script)
+    }
+
+    fn visit_member(&mut self, member: &Member) {
+        walk_member(self, member)
+    }
+
+    fn visit_import(&mut self, _import: &Import) {}
+
+    fn visit_custom_event(&mut self, _event: &CustomEvent) {}
+
+    fn visit_property(&mut self, property: &Property) {
+        walk_property(self, property)
+    }
+
+    fn visit_group(&mut self, group: &Group) {
+        walk_group(self, group)
+    }
+
+    fn visit_function(&mut self, function: &Function) {
+        walk_function(self, function)
+    }
+
+    fn visit_event(&mut self, event: &Event) {
+        walk_event(self, event)
+    }
+
+    fn visit_state(&mut self, state: &State) {
+        walk_state(self, state)
+    }
+
+    fn visit_struct(&mut self, item: &Struct) {
+        walk_struct(self, item)
+    }
+
+    fn visit_param(&mut self, param: &Param) {
+        walk_param(self, param)
+    }
+
+    fn visit_var_decl(&mut self, decl: &VarDecl) {
+        walk_var_decl(self, decl)
+    }
+
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        walk_stmt(self, stmt)
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        walk_expr(self, expr)
+    }
+
+    fn visit_arg(&mut self, arg: &Arg) {
+        walk_arg(self, arg)
+    }
+}
+
+pub fn walk_script<V: Visitor + ?Sized>(visitor: &mut V, script: &Script) {
+    for member in &script.members {
+        visitor.visit_member(member);
+    }
+}
+
+pub fn walk_member<V: Visitor + ?Sized>(visitor: &mut V, member: &Member) {
+    match member {
+        Member::Import(import) => visitor.visit_import(import),
+        Member::CustomEvent(event) => visitor.visit_custom_event(event),
+        Member::Property(property) => visitor.visit_property(property),
+        Member::Group(group) => visitor.visit_group(group),
+        Member::Function(function) => visitor.visit_function(function),
+        Member::Event(event) => visitor.visit_event(event),
+        Member::State(state) => visitor.visit_state(state),
+        Member::Struct(item) => visitor.visit_struct(item),
+        Member::Var(decl) => visitor.visit_var_decl(decl),
+    }
+}
+
+pub fn walk_property<V: Visitor + ?Sized>(visitor: &mut V, property: &Property) {
+    if let Some(init) = &property.init {
+        visitor.visit_expr(init);
+    }
+    for accessor in property.getter.iter().chain(&property.setter) {
+        visitor.visit_function(accessor);
+    }
+}
+
+pub fn walk_group<V: Visitor + ?Sized>(visitor: &mut V, group: &Group) {
+    for property in &group.properties {
+        visitor.visit_property(property);
+    }
+}
+
+pub fn walk_function<V: Visitor + ?Sized>(visitor: &mut V, function: &Function) {
+    for param in &function.params {
+        visitor.visit_param(param);
+    }
+    for stmt in &function.body {
+        visitor.visit_stmt(stmt);
+    }
+}
+
+pub fn walk_event<V: Visitor + ?Sized>(visitor: &mut V, event: &Event) {
+    for param in &event.params {
+        visitor.visit_param(param);
+    }
+    for stmt in &event.body {
+        visitor.visit_stmt(stmt);
+    }
+}
+
+pub fn walk_state<V: Visitor + ?Sized>(visitor: &mut V, state: &State) {
+    for member in &state.members {
+        match member {
+            StateMember::Function(function) => visitor.visit_function(function),
+            StateMember::Event(event) => visitor.visit_event(event),
+        }
+    }
+}
+
+pub fn walk_struct<V: Visitor + ?Sized>(visitor: &mut V, item: &Struct) {
+    for field in &item.fields {
+        visitor.visit_var_decl(field);
+    }
+}
+
+pub fn walk_param<V: Visitor + ?Sized>(visitor: &mut V, param: &Param) {
+    if let Some(default) = &param.default {
+        visitor.visit_expr(default);
+    }
+}
+
+pub fn walk_var_decl<V: Visitor + ?Sized>(visitor: &mut V, decl: &VarDecl) {
+    if let Some(init) = &decl.init {
+        visitor.visit_expr(init);
+    }
+}
+
+pub fn walk_stmt<V: Visitor + ?Sized>(visitor: &mut V, stmt: &Stmt) {
+    match stmt {
+        Stmt::Var(decl) => visitor.visit_var_decl(decl),
+        Stmt::Assign { target, value, .. } => {
+            visitor.visit_expr(target);
+            visitor.visit_expr(value);
+        }
+        Stmt::Return { value, .. } => {
+            if let Some(value) = value {
+                visitor.visit_expr(value);
+            }
+        }
+        Stmt::If { arms, else_block, .. } => {
+            for arm in arms {
+                visitor.visit_expr(&arm.cond);
+                for stmt in &arm.body {
+                    visitor.visit_stmt(stmt);
+                }
+            }
+            for stmt in else_block.iter().flatten() {
+                visitor.visit_stmt(stmt);
+            }
+        }
+        Stmt::While { cond, body, .. } => {
+            visitor.visit_expr(cond);
+            for stmt in body {
+                visitor.visit_stmt(stmt);
+            }
+        }
+        Stmt::Expr(expr) => visitor.visit_expr(expr),
+    }
+}
+
+pub fn walk_expr<V: Visitor + ?Sized>(visitor: &mut V, expr: &Expr) {
+    match expr {
+        Expr::Unary { expr, .. }
+        | Expr::Cast { expr, .. }
+        | Expr::TypeCheck { expr, .. }
+        | Expr::Paren { expr, .. } => visitor.visit_expr(expr),
+        Expr::Binary { lhs, rhs, .. } => {
+            visitor.visit_expr(lhs);
+            visitor.visit_expr(rhs);
+        }
+        Expr::Call { callee, args, .. } => {
+            visitor.visit_expr(callee);
+            for arg in args {
+                visitor.visit_arg(arg);
+            }
+        }
+        Expr::Member { base, .. } => visitor.visit_expr(base),
+        Expr::Index { base, index, .. } => {
+            visitor.visit_expr(base);
+            visitor.visit_expr(index);
+        }
+        Expr::NewArray { size, .. } => visitor.visit_expr(size),
+        Expr::Literal { .. }
+        | Expr::Ident { .. }
+        | Expr::NewStruct { .. }
+        | Expr::SelfRef { .. }
+        | Expr::ParentRef { .. } => (),
+    }
+}
+
+pub fn walk_arg<V: Visitor + ?Sized>(visitor: &mut V, arg: &Arg) {
+    visitor.visit_expr(&arg.value)
+}
+
+/// Equality ignoring spans, to compare ASTs parsed from differently formatted sources.
+pub trait SpanlessEq {
+    fn spanless_eq(&self, other: &Self) -> bool;
+}
+
+/// Whether `a` and `b` are equal apart from their spans.
+pub fn structurally_eq<T: SpanlessEq>(a: &T, b: &T) -> bool {
+    a.spanless_eq(b)
+}
+
+impl<T: SpanlessEq> SpanlessEq for Box<T> {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        (**self).spanless_eq(other)
+    }
+}
+
+impl<T: SpanlessEq> SpanlessEq for Option<T> {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Some(a), Some(b)) => a.spanless_eq(b),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+}
+
+impl<T: SpanlessEq> SpanlessEq for Vec<T> {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().zip(other).all(|(a, b)| a.spanless_eq(b))
+    }
+}
+
+// nodes without spans compare as usual
+macro_rules! spanless_eq_by_partial_eq {
+    ($($ty:ty),*) => {
+        $(
+            impl SpanlessEq for $ty {
+                fn spanless_eq(&self, other: &Self) -> bool {
+                    self == other
+                }
+            }
+        )*
+    };
+}
+
+spanless_eq_by_partial_eq!(
+    String,
+    Type,
+    ScriptFlag,
+    PropertyKind,
+    PropertyFlag,
+    FnFlag,
+    VarFlag,
+    AssignOp,
+    UnOp,
+    BinOp
+);
+
+impl SpanlessEq for Script {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        self.header.spanless_eq(&other.header) && self.members.spanless_eq(&other.members)
+    }
+}
+
+impl SpanlessEq for ScriptHeader {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.extends == other.extends
+            && self.flags == other.flags
+            && self.doc == other.doc
+    }
+}
+
+impl SpanlessEq for Member {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Member::Import(a), Member::Import(b)) => a.name == b.name,
+            (Member::CustomEvent(a), Member::CustomEvent(b)) => a.name == b.name,
+            (Member::Property(a), Member::Property(b)) => a.spanless_eq(b),
+            (Member::Group(a), Member::Group(b)) => a.spanless_eq(b),
+            (Member::Function(a), Member::Function(b)) => a.spanless_eq(b),
+            (Member::Event(a), Member::Event(b)) => a.spanless_eq(b),
+            (Member::State(a), Member::State(b)) => a.spanless_eq(b),
+            (Member::Struct(a), Member::Struct(b)) => a.spanless_eq(b),
+            (Member::Var(a), Member::Var(b)) => a.spanless_eq(b),
+            _ => false,
+        }
+    }
+}
+
+impl SpanlessEq for Property {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        self.ty == other.ty
+            && self.name == other.name
+            && self.kind == other.kind
+            && self.flags == other.flags
+            && self.init.spanless_eq(&other.init)
+            && self.getter.spanless_eq(&other.getter)
+            && self.setter.spanless_eq(&other.setter)
+            && self.doc == other.doc
+    }
+}
+
+impl SpanlessEq for Group {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.flags == other.flags
+            && self.properties.spanless_eq(&other.properties)
+    }
+}
+
+impl SpanlessEq for Param {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        self.ty == other.ty && self.name == other.name && self.default.spanless_eq(&other.default)
+    }
+}
+
+impl SpanlessEq for Function {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        self.return_type == other.return_type
+            && self.name == other.name
+            && self.params.spanless_eq(&other.params)
+            && self.flags == other.flags
+            && self.body.spanless_eq(&other.body)
+            && self.doc == other.doc
+    }
+}
+
+impl SpanlessEq for Event {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.params.spanless_eq(&other.params)
+            && self.is_native == other.is_native
+            && self.body.spanless_eq(&other.body)
+            && self.doc == other.doc
+    }
+}
+
+impl SpanlessEq for StateMember {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (StateMember::Function(a), StateMember::Function(b)) => a.spanless_eq(b),
+            (StateMember::Event(a), StateMember::Event(b)) => a.spanless_eq(b),
+            _ => false,
+        }
+    }
+}
+
+impl SpanlessEq for State {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.is_auto == other.is_auto
+            && self.members.spanless_eq(&other.members)
+    }
+}
+
+impl SpanlessEq for VarDecl {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        self.ty == other.ty
+            && self.name == other.name
+            && self.init.spanless_eq(&other.init)
+            && self.flags == other.flags
+    }
+}
+
+impl SpanlessEq for Struct {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.fields.spanless_eq(&other.fields)
+    }
+}
+
+impl SpanlessEq for IfArm {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        self.cond.spanless_eq(&other.cond) && self.body.spanless_eq(&other.body)
+    }
+}
+
+impl SpanlessEq for Stmt {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Stmt::Var(a), Stmt::Var(b)) => a.spanless_eq(b),
+            (
+                Stmt::Assign { target, op, value, .. },
+                Stmt::Assign { target: target2, op: op2, value: value2, .. },
+            ) => target.spanless_eq(target2) && op == op2 && value.spanless_eq(value2),
+            (Stmt::Return { value, .. }, Stmt::Return { value: value2, .. }) => {
+                value.spanless_eq(value2)
+            }
+            (
+                Stmt::If { arms, else_block, .. },
+                Stmt::If { arms: arms2, else_block: else_block2, .. },
+            ) => arms.spanless_eq(arms2) && else_block.spanless_eq(else_block2),
+            (Stmt::While { cond, body, .. }, Stmt::While { cond: cond2, body: body2, .. }) => {
+                cond.spanless_eq(cond2) && body.spanless_eq(body2)
+            }
+            (Stmt::Expr(a), Stmt::Expr(b)) => a.spanless_eq(b),
+            _ => false,
+        }
+    }
+}
+
+// the raw text of a float is formatting, only its value counts
+impl SpanlessEq for LitKind {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (LitKind::Float(value, _), LitKind::Float(value2, _)) => value == value2,
+            _ => self == other,
+        }
+    }
+}
+
+impl SpanlessEq for Arg {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.value.spanless_eq(&other.value)
+    }
+}
+
+impl SpanlessEq for Expr {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Expr::Literal { lit, .. }, Expr::Literal { lit: lit2, .. }) => lit.spanless_eq(lit2),
+            (Expr::Ident { name, .. }, Expr::Ident { name: name2, .. }) => name == name2,
+            (Expr::Unary { op, expr, .. }, Expr::Unary { op: op2, expr: expr2, .. }) => {
+                op == op2 && expr.spanless_eq(expr2)
+            }
+            (
+                Expr::Binary { op, lhs, rhs, .. },
+                Expr::Binary { op: op2, lhs: lhs2, rhs: rhs2, .. },
+            ) => op == op2 && lhs.spanless_eq(lhs2) && rhs.spanless_eq(rhs2),
+            (Expr::Call { callee, args, .. }, Expr::Call { callee: callee2, args: args2, .. }) => {
+                callee.spanless_eq(callee2) && args.spanless_eq(args2)
+            }
+            (Expr::Member { base, field, .. }, Expr::Member { base: base2, field: field2, .. }) => {
+                base.spanless_eq(base2) && field == field2
+            }
+            (Expr::Index { base, index, .. }, Expr::Index { base: base2, index: index2, .. }) => {
+                base.spanless_eq(base2) && index.spanless_eq(index2)
+            }
+            (Expr::Cast { expr, ty, .. }, Expr::Cast { expr: expr2, ty: ty2, .. })
+            | (Expr::TypeCheck { expr, ty, .. }, Expr::TypeCheck { expr: expr2, ty: ty2, .. }) => {
+                expr.spanless_eq(expr2) && ty == ty2
+            }
+            (Expr::Paren { expr, .. }, Expr::Paren { expr: expr2, .. }) => expr.spanless_eq(expr2),
+            (Expr::NewArray { ty, size, .. }, Expr::NewArray { ty: ty2, size: size2, .. }) => {
+                ty == ty2 && size.spanless_eq(size2)
+            }
+            (Expr::NewStruct { name, .. }, Expr::NewStruct { name: name2, .. }) => name == name2,
+            (Expr::SelfRef { .. }, Expr::SelfRef { .. })
+            | (Expr::ParentRef { .. }, Expr::ParentRef { .. }) => true,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Game, Parser, ParserSession};
+
+    #[test]
+    fn span() {
+        let lhs = Expr::Ident { name: "a".to_string(), span: (0, 1) };
+        let rhs = Expr::Literal { lit: LitKind::Integer(1, false), span: (4, 5) };
+        let expr =
+            Expr::Binary { op: BinOp::Add, lhs: Box::new(lhs), rhs: Box::new(rhs), span: (0, 5) };
+        assert_eq!((0, 5), expr.span());
+        assert_eq!((0, 5), Stmt::Expr(expr).span());
+    }
+
+    #[test]
+    fn visitor() {
+        struct CountFunctions(usize);
+
+        impl Visitor for CountFunctions {
+            fn visit_function(&mut self, function: &Function) {
+                self.0 += 1;
+                walk_function(self, function)
+            }
+        }
+
+        let script = "ScriptName Foo\n\
+                      Function A()\n\
+                      EndFunction\n\
+                      Int Function B() Native\n\
+                      State Waiting\n\
+                      \tEvent OnInit()\n\
+                      \tEndEvent\n\
+                      \tFunction A()\n\
+                      \tEndFunction\n\
+                      EndState";
+        let sess = ParserSession::from_string(script, Game::TESV);
+        let script = Parser::from_sess(&sess).parse_script().unwrap();
+        let mut counter = CountFunctions(0);
+        counter.visit_script(&script);
+        assert_eq!(3, counter.0);
+    }
+
+    #[test]
+    fn structurally_eq() {
+        let parse = |source: &str| {
+            let sess = ParserSession::from_string(source, Game::TESV);
+            let script = Parser::from_sess(&sess).parse_script().unwrap();
+            script
+        };
+        let a = parse("ScriptName Foo\nInt Function Bar(Int x = 1)\n\tReturn x + 1\nEndFunction");
+        let b = parse("ScriptName  Foo\n\nint function Bar( int x=1 )\nreturn x+1\nendfunction");
+        assert_ne!(a, b);
+        assert!(super::structurally_eq(&a, &b));
+        let c = parse("ScriptName Foo\nInt Function Bar(Int x = 1)\n\tReturn x + 2\nEndFunction");
+        assert!(!super::structurally_eq(&a, &c));
+        let d = parse("ScriptName Foo\nFloat x = 1.5\n");
+        let e = parse("ScriptName Foo\nFloat x = 1.50\n");
+        assert!(super::structurally_eq(&d, &e));
+    }
+}
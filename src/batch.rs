@@ -0,0 +1,139 @@
+use std::fs;
+use std::io;
+use std::panic::{self, UnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+
+use annotate_snippets::snippet::AnnotationType;
+
+use crate::ast::Script;
+use crate::{Diagnostic, Game, Parser, ParserSession};
+
+/// The outcome of parsing one file with `parse_dir`.
+pub type ScriptResult = Result<Script, Vec<Diagnostic>>;
+
+/// Parses every `.psc` file under `path`, recursively, on as many threads as there are cores.
+///
+/// A file fails with its diagnostics if it could not be parsed or has any error, without stopping
+/// the others. A panic while parsing a file only fails that file. Results are sorted by path.
+/// Only an error reading the directories themselves is returned as `Err`.
+pub fn parse_dir<P: AsRef<Path>>(path: P, game: Game) -> io::Result<Vec<(PathBuf, ScriptResult)>> {
+    let mut paths = vec![];
+    find_scripts(path.as_ref(), &mut paths)?;
+
+    let workers = thread::available_parallelism().map_or(1, |n| n.get()).min(paths.len());
+    let queue = Mutex::new(paths.into_iter());
+    let mut results = thread::scope(|scope| {
+        let handles = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut results = vec![];
+                    // the lock is released as soon as the next path is taken, and the iterator
+                    // is still usable if another worker panicked while holding it
+                    while let Some(path) =
+                        queue.lock().unwrap_or_else(PoisonError::into_inner).next()
+                    {
+                        let result = catch_panic(|| parse_file(&path, game));
+                        results.push((path, result));
+                    }
+                    results
+                })
+            })
+            .collect::<Vec<_>>();
+        // parsing panics are caught per file, so workers only panic on a bug in this function
+        handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect::<Vec<_>>()
+    });
+    results.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(results)
+}
+
+fn find_scripts(dir: &Path, paths: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_scripts(&path, paths)?;
+        } else if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("psc")) {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn parse_file(path: &Path, game: Game) -> ScriptResult {
+    let sess = match ParserSession::from_file(path, game) {
+        Ok(sess) => sess,
+        Err(err) => return Err(vec![file_error(&format!("could not read script: {}", err))]),
+    };
+    let script = Parser::from_sess(&sess).parse_script();
+    let diagnostics = sess.diagnostics();
+    let has_error =
+        diagnostics.iter().any(|diagnostic| matches!(diagnostic.level, AnnotationType::Error));
+    match script {
+        Ok(script) if !has_error => Ok(script),
+        _ => Err(diagnostics.to_vec()),
+    }
+}
+
+// fails a file whose parse panicked instead of taking down the whole batch
+fn catch_panic(parse: impl FnOnce() -> ScriptResult + UnwindSafe) -> ScriptResult {
+    panic::catch_unwind(parse).unwrap_or_else(|payload| {
+        let reason = match (payload.downcast_ref::<&str>(), payload.downcast_ref::<String>()) {
+            (Some(reason), _) => reason.to_string(),
+            (_, Some(reason)) => reason.clone(),
+            _ => "unknown panic".to_string(),
+        };
+        Err(vec![file_error(&format!("internal error while parsing script: {}", reason))])
+    })
+}
+
+// a diagnostic for a whole file, which has no source to point into
+fn file_error(title: &str) -> Diagnostic {
+    Diagnostic {
+        code: None,
+        title: title.to_string(),
+        level: AnnotationType::Error,
+        span: (0, 0),
+        label: None,
+        label_level: None,
+        labels: vec![],
+        footer: vec![],
+        fatal: true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn parse_dir() {
+        let dir = env::temp_dir().join("libpapyrus_parse_dir");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("Sub")).unwrap();
+        fs::write(dir.join("Valid.psc"), "ScriptName Valid\nInt x = 1\n").unwrap();
+        fs::write(dir.join("Sub").join("Broken.psc"), "ScriptName Broken\nInt x = \n").unwrap();
+        fs::write(dir.join("notes.txt"), "not a script").unwrap();
+        let results = super::parse_dir(&dir, Game::TESV).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(2, results.len());
+        assert_eq!(dir.join("Sub").join("Broken.psc"), results[0].0);
+        assert!(matches!(&results[0].1, Err(diagnostics) if !diagnostics.is_empty()));
+        assert_eq!(dir.join("Valid.psc"), results[1].0);
+        assert!(matches!(&results[1].1, Ok(script) if script.header.name == "Valid"));
+    }
+
+    #[test]
+    fn catch_panic() {
+        let result = super::catch_panic(|| panic!("oops"));
+        match result {
+            Err(diagnostics) => {
+                assert_eq!("internal error while parsing script: oops", diagnostics[0].title)
+            }
+            Ok(_) => panic!("expected the panic to fail the file"),
+        }
+    }
+}
@@ -0,0 +1,193 @@
+//! A lossless concrete syntax tree.
+//!
+//! Unlike the AST, the tree keeps every token including whitespace, newlines, comments and
+//! documentation, so concatenating the text of its tokens gives back the original source. It only
+//! groups tokens into lines and nested blocks, which is enough to edit part of a script while
+//! preserving the formatting everywhere else.
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::lexer::{KwKind, Lexer, Token, TokenKind};
+use crate::ParserSession;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum NodeKind {
+    Script,
+    /// Every token of a line, including its trailing newline.
+    Line,
+    /// A line opening a block such as `Function` or `If`, the lines inside it and the line
+    /// closing it, if any.
+    Block,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SyntaxToken {
+    pub token: Token,
+    pub text: String,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SyntaxNode {
+    pub kind: NodeKind,
+    pub children: Vec<SyntaxElement>,
+}
+
+impl SyntaxNode {
+    fn new(kind: NodeKind) -> Self {
+        Self { kind, children: vec![] }
+    }
+
+    /// Builds the tree for the whole source.
+    ///
+    /// The lexer runs in recovery mode so even invalid source is covered by tokens.
+    pub fn build(sess: &ParserSession) -> Self {
+        let mut lexer = Lexer::from_sess(sess);
+        lexer.set_recover(true);
+        let content = &sess.src.content;
+        let mut stack = vec![SyntaxNode::new(NodeKind::Script)];
+        let mut line = vec![];
+        for token in lexer {
+            let ends_line = matches!(token.kind, TokenKind::Newline(_) | TokenKind::Eof);
+            let text = content[token.lo()..token.hi()].to_string();
+            line.push(SyntaxToken { token, text });
+            if ends_line {
+                push_line(&mut stack, std::mem::take(&mut line));
+            }
+        }
+        // blocks left open at the end of the file
+        while stack.len() > 1 {
+            let block = stack.pop().unwrap();
+            stack.last_mut().unwrap().children.push(SyntaxElement::Node(block));
+        }
+        stack.pop().unwrap()
+    }
+
+    /// The source text this node covers.
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        self.write_text(&mut text);
+        text
+    }
+
+    fn write_text(&self, text: &mut String) {
+        for child in &self.children {
+            match child {
+                SyntaxElement::Node(node) => node.write_text(text),
+                SyntaxElement::Token(token) => text.push_str(&token.text),
+            }
+        }
+    }
+}
+
+fn line_keywords(line: &[SyntaxToken]) -> impl Iterator<Item = &KwKind> {
+    line.iter().filter_map(|token| match &token.token.kind {
+        TokenKind::Keyword(kw) => Some(kw),
+        _ => None,
+    })
+}
+
+fn opens_block(line: &[SyntaxToken]) -> bool {
+    let has = |kw: KwKind| line_keywords(line).any(|other| *other == kw);
+    // declarations may start with a return or property type
+    let first = line_keywords(line).find(|kw| {
+        !matches!(kw, KwKind::Bool | KwKind::Float | KwKind::Int | KwKind::String | KwKind::Var)
+    });
+    match first {
+        Some(KwKind::If) | Some(KwKind::While) | Some(KwKind::Struct) | Some(KwKind::Group) => true,
+        Some(KwKind::State) | Some(KwKind::Auto) => has(KwKind::State),
+        // native functions and events have no body
+        Some(KwKind::Function) | Some(KwKind::Event) => !has(KwKind::Native),
+        Some(KwKind::Property) => !has(KwKind::Auto) && !has(KwKind::AutoReadOnly),
+        _ => false,
+    }
+}
+
+fn closes_block(line: &[SyntaxToken]) -> bool {
+    let first = line
+        .iter()
+        .find(|token| !matches!(token.token.kind, TokenKind::Whitespace | TokenKind::Comment(_)));
+    match first.map(|token| &token.token.kind) {
+        Some(TokenKind::Keyword(kw)) => matches!(
+            kw,
+            KwKind::EndEvent
+                | KwKind::EndFunction
+                | KwKind::EndGroup
+                | KwKind::EndIf
+                | KwKind::EndProperty
+                | KwKind::EndState
+                | KwKind::EndStruct
+                | KwKind::EndWhile
+        ),
+        _ => false,
+    }
+}
+
+fn push_line(stack: &mut Vec<SyntaxNode>, line: Vec<SyntaxToken>) {
+    let closes = stack.len() > 1 && closes_block(&line);
+    let opens = !closes && opens_block(&line);
+    let mut node = SyntaxNode::new(NodeKind::Line);
+    node.children = line.into_iter().map(SyntaxElement::Token).collect();
+    if opens {
+        let mut block = SyntaxNode::new(NodeKind::Block);
+        block.children.push(SyntaxElement::Node(node));
+        stack.push(block);
+        return;
+    }
+    stack.last_mut().unwrap().children.push(SyntaxElement::Node(node));
+    if closes {
+        let block = stack.pop().unwrap();
+        stack.last_mut().unwrap().children.push(SyntaxElement::Node(block));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Game;
+
+    fn node(element: &SyntaxElement) -> &SyntaxNode {
+        match element {
+            SyntaxElement::Node(node) => node,
+            SyntaxElement::Token(token) => panic!("expected node, found {:?}", token),
+        }
+    }
+
+    #[test]
+    fn reassemble() {
+        let source = "ScriptName   Foo ; the script\r\n\
+                      {docs}\n\
+                      Int  Function Bar( Int a )   \n\
+                      \t;/ block\n\
+                      comment /;  If a\t==1\n\
+                      \t\tReturn  a\n\
+                      \tEndIf\n\
+                      EndFunction\n\
+                      \n\
+                      Function Baz() Native\n\
+                      \"unterminated";
+        let sess = ParserSession::from_string(source, Game::TESV);
+        let cst = SyntaxNode::build(&sess);
+        assert_eq!(source, cst.text());
+
+        assert_eq!(NodeKind::Script, cst.kind);
+        let kinds = cst.children.iter().map(|child| node(child).kind).collect::<Vec<_>>();
+        use NodeKind::{Block, Line};
+        assert_eq!(vec![Line, Line, Block, Line, Line, Line], kinds);
+        let function = node(&cst.children[2]);
+        assert_eq!(3, function.children.len());
+        let if_block = node(&function.children[1]);
+        assert_eq!(Block, if_block.kind);
+        assert_eq!("\tEndIf\n", node(&if_block.children[2]).text());
+    }
+}
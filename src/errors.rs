@@ -1,4 +1,7 @@
-use std::panic;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Write};
+use std::path::PathBuf;
 
 use annotate_snippets::display_list::DisplayList;
 use annotate_snippets::formatter::DisplayListFormatter;
@@ -6,18 +9,124 @@ use annotate_snippets::snippet::{Annotation, AnnotationType, Slice, Snippet, Sou
 
 use crate::ParserSession;
 
-struct FatalMarker;
+/// Error returned when a `ParserSession` cannot be created from a file.
+#[derive(Debug)]
+pub enum SessionError {
+    NotAFile(PathBuf),
+    NoFileName(PathBuf),
+    Io(io::Error),
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionError::NotAFile(path) => write!(f, "{} is not a file", path.display()),
+            SessionError::NoFileName(path) => {
+                write!(f, "could not find file name of {}", path.display())
+            }
+            SessionError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for SessionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SessionError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SessionError {
+    fn from(err: io::Error) -> Self {
+        SessionError::Io(err)
+    }
+}
+
+/// Error returned when parsing a `Game` from an unknown name.
+#[derive(PartialEq, Debug)]
+pub struct ParseGameError(pub String);
+
+impl fmt::Display for ParseGameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown game '{}', expected one of: tesv, skyrim, fo4, fallout4", self.0)
+    }
+}
+
+impl Error for ParseGameError {}
+
+/// Stable codes identifying each kind of diagnostic, so tools can filter on them.
+///
+/// Errors start with `E` and warnings with `W`. Codes are never reused for a different diagnostic.
+pub mod codes {
+    pub const UNTERMINATED_STRING: &str = "E0001";
+    pub const UNTERMINATED_DOC: &str = "E0002";
+    pub const UNTERMINATED_BLOCK_COMMENT: &str = "E0003";
+    pub const INVALID_ESCAPE: &str = "E0004";
+    pub const INVALID_UNICODE_ESCAPE: &str = "E0005";
+    pub const INVALID_UTF8: &str = "E0006";
+    pub const UNKNOWN_LEXEME: &str = "E0007";
+    pub const INVALID_INTEGER: &str = "E0008";
+    pub const INVALID_HEX: &str = "E0009";
+    pub const INVALID_FLOAT: &str = "E0010";
+    pub const MULTIPLE_DECIMAL_POINTS: &str = "E0011";
+    pub const MISSING_EXPONENT: &str = "E0012";
+    pub const INVALID_NAMESPACE: &str = "E0013";
+    pub const READ_FAILED: &str = "E0014";
+    pub const SINGLE_PIPE: &str = "W0001";
+    pub const SINGLE_AMPERSAND: &str = "W0002";
+    pub const IDENT_TOO_LONG: &str = "W0003";
+    pub const LINE_TOO_LONG: &str = "W0004";
+}
+
+/// An extra annotation pointing at another part of the source.
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub span: (usize, usize),
+    pub level: AnnotationType,
+    pub text: String,
+}
+
+/// A diagnostic emitted while lexing or parsing.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    /// One of the `codes`, if any.
+    pub code: Option<String>,
+    pub title: String,
+    pub level: AnnotationType,
+    pub span: (usize, usize),
+    pub label: Option<String>,
+    pub label_level: Option<AnnotationType>,
+    pub labels: Vec<Label>,
+    /// Notes rendered below the snippet.
+    pub footer: Vec<(AnnotationType, String)>,
+    pub fatal: bool,
+}
+
+impl Diagnostic {
+    /// Pretty-prints the diagnostic with a snippet of the source of `sess`, which emitted it.
+    ///
+    /// This is the text `emit` writes to the session output, for tools showing diagnostics in
+    /// their own UI.
+    pub fn render(&self, sess: &ParserSession, colors: bool) -> String {
+        render(self, sess, colors)
+    }
+}
 
 pub struct ErrorBuilder<'a> {
     sess: &'a ParserSession,
     colors: bool,
     fatal: bool,
+    code: Option<String>,
     title: Option<String>,
     level: AnnotationType,
     lo: usize,
     hi: usize,
     label: Option<String>,
     label_level: Option<AnnotationType>,
+    labels: Vec<Label>,
+    footer: Vec<(AnnotationType, String)>,
 }
 
 impl<'a> ErrorBuilder<'a> {
@@ -26,12 +135,15 @@ impl<'a> ErrorBuilder<'a> {
             sess,
             colors,
             fatal: false,
+            code: None,
             title: None,
             level: AnnotationType::Info,
             lo: 0,
             hi: 0,
             label: None,
             label_level: None,
+            labels: vec![],
+            footer: vec![],
         }
     }
 
@@ -58,6 +170,11 @@ impl<'a> ErrorBuilder<'a> {
         self
     }
 
+    pub fn code(&mut self, code: &str) -> &mut Self {
+        self.code = Some(code.to_string());
+        self
+    }
+
     pub fn span(&mut self, lo: usize, hi: usize) -> &mut Self {
         self.lo = lo;
         self.hi = hi;
@@ -82,42 +199,294 @@ impl<'a> ErrorBuilder<'a> {
         self
     }
 
+    /// Adds another annotation, on top of the one at `span`, that is rendered in the same snippet.
+    pub fn label_at(
+        &mut self,
+        lo: usize,
+        hi: usize,
+        level: AnnotationType,
+        text: &str,
+    ) -> &mut Self {
+        self.labels.push(Label { span: (lo, hi), level, text: text.to_string() });
+        self
+    }
+
+    pub fn note(&mut self, note: &str) -> &mut Self {
+        self.footer.push((AnnotationType::Note, note.to_string()));
+        self
+    }
+
+    pub fn footer_help(&mut self, help: &str) -> &mut Self {
+        self.footer.push((AnnotationType::Help, help.to_string()));
+        self
+    }
+
+    pub fn build(&self) -> Diagnostic {
+        Diagnostic {
+            code: self.code.clone(),
+            title: self.title.clone().unwrap_or_default(),
+            level: self.level,
+            span: (self.lo, self.hi),
+            label: self.label.clone(),
+            label_level: self.label_level,
+            labels: self.labels.clone(),
+            footer: self.footer.clone(),
+            fatal: self.fatal,
+        }
+    }
+
+    /// Records the diagnostic and prints it to the session output, if any.
+    ///
+    /// Non-fatal diagnostics below the session's minimum level are dropped.
     pub fn emit(&self) {
-        let (lo_line, lo_col) = self.sess.src.lineno_from_offset(self.lo);
-        let (hi_line, hi_col) = self.sess.src.lineno_from_offset(self.hi);
-        let source_list = self.sess.src.lines_from_linenos(lo_line, hi_line);
-        if source_list.is_empty() {
-            panic!("Source list cannot be empty - internal bug in error creation.")
+        if self.is_dropped() {
+            return;
         }
-        let lo = lo_col;
-        let source_len = source_list.len();
-        let mut hi = hi_col;
-        if source_len > 1 {
-            // add remainder length of first line
-            hi += source_list.first().unwrap().len() - lo_col
-        };
-        if source_list.len() > 2 {
-            // add all line length between first and last
-            hi += source_list[1..source_list.len() - 1].iter().map(|x| x.len()).sum::<usize>()
-        };
-        let formatter = DisplayListFormatter::new(self.colors, false);
-        let title = Annotation { id: None, label: self.title.clone(), annotation_type: self.level };
-        let annotatation = SourceAnnotation {
-            range: (lo, hi),
-            label: self.label.clone().unwrap_or_else(|| "".to_string()),
-            annotation_type: self.label_level.unwrap_or(self.level),
-        };
-        let slices = vec![Slice {
-            source: source_list.join(""),
-            line_start: 1,
-            origin: Some(self.sess.src.filename.to_string_lossy().to_string()),
-            fold: true,
-            annotations: vec![annotatation],
-        }];
-        let snippet = Snippet { title: Some(title), footer: vec![], slices };
-        eprintln!("{}", formatter.format(&DisplayList::from(snippet)));
-        if self.fatal {
-            panic::resume_unwind(Box::new(FatalMarker));
+        let diagnostic = self.build();
+        if let Some(output) = self.sess.output.borrow_mut().as_mut() {
+            // a broken sink must not stop parsing, the diagnostic is still recorded
+            let _ = writeln!(output, "{}", render(&diagnostic, self.sess, self.colors));
+        }
+        self.sess.diagnostics.borrow_mut().push(diagnostic);
+    }
+
+    /// Like `emit`, but pretty-prints the diagnostic to `w` instead of the session output.
+    pub fn emit_to(&self, w: &mut dyn Write) -> io::Result<()> {
+        if self.is_dropped() {
+            return Ok(());
         }
+        let diagnostic = self.build();
+        let result = writeln!(w, "{}", render(&diagnostic, self.sess, self.colors));
+        self.sess.diagnostics.borrow_mut().push(diagnostic);
+        result
+    }
+
+    // whether the diagnostic is below the session's minimum level and not fatal
+    fn is_dropped(&self) -> bool {
+        !self.fatal && severity(self.level) < severity(self.sess.min_level)
+    }
+}
+
+// rank of `level`, higher is more severe
+fn severity(level: AnnotationType) -> u8 {
+    match level {
+        AnnotationType::Error => 4,
+        AnnotationType::Warning => 3,
+        AnnotationType::Info => 2,
+        AnnotationType::Note => 1,
+        AnnotationType::Help => 0,
+    }
+}
+
+// offset of the character at `(line, col)` into the joined `lines`, which start at `first_line`
+fn slice_offset(lines: &[String], first_line: usize, (line, col): (usize, usize)) -> usize {
+    lines[..line - first_line].iter().map(|x| x.chars().count()).sum::<usize>() + col
+}
+
+fn render(diagnostic: &Diagnostic, sess: &ParserSession, colors: bool) -> String {
+    let spans = || std::iter::once(diagnostic.span).chain(diagnostic.labels.iter().map(|l| l.span));
+    // the snippet covers every line annotated by the diagnostic
+    let lo_line = spans().map(|span| sess.src.lineno_from_offset(span.0, 1).0).min().unwrap();
+    let hi_line = spans().map(|span| sess.src.lineno_from_offset(span.1, 1).0).max().unwrap();
+    // annotate-snippets counts every line break as one character, so CRLF endings are rendered
+    // as LF to keep offsets on later lines from drifting
+    let source_list = sess
+        .src
+        .lines_from_linenos(lo_line, hi_line)
+        .into_iter()
+        .map(|line| match line.strip_suffix("\r\n") {
+            Some(line) => format!("{}\n", line),
+            None => line,
+        })
+        .collect::<Vec<_>>();
+    if source_list.is_empty() {
+        panic!("Source list cannot be empty - internal bug in error creation.")
+    }
+    let range = |(lo, hi): (usize, usize)| {
+        let lo = slice_offset(&source_list, lo_line, sess.src.lineno_from_offset(lo, 1));
+        let hi = slice_offset(&source_list, lo_line, sess.src.lineno_from_offset(hi, 1));
+        (lo, hi)
+    };
+    let formatter = DisplayListFormatter::new(colors, false);
+    let title = Annotation {
+        id: diagnostic.code.clone(),
+        label: Some(diagnostic.title.clone()),
+        annotation_type: diagnostic.level,
+    };
+    let mut annotations = vec![SourceAnnotation {
+        range: range(diagnostic.span),
+        label: diagnostic.label.clone().unwrap_or_default(),
+        annotation_type: diagnostic.label_level.unwrap_or(diagnostic.level),
+    }];
+    annotations.extend(diagnostic.labels.iter().map(|label| SourceAnnotation {
+        range: range(label.span),
+        label: label.text.clone(),
+        annotation_type: label.level,
+    }));
+    let slices = vec![Slice {
+        source: source_list.join(""),
+        line_start: lo_line,
+        origin: Some(sess.src.filename.to_string_lossy().to_string()),
+        fold: true,
+        annotations,
+    }];
+    let footer = diagnostic
+        .footer
+        .iter()
+        .map(|(level, text)| Annotation {
+            id: None,
+            label: Some(text.clone()),
+            annotation_type: *level,
+        })
+        .collect();
+    let snippet = Snippet { title: Some(title), footer, slices };
+    formatter.format(&DisplayList::from(snippet))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Game, Lexer};
+
+    #[test]
+    fn build() {
+        let sess = ParserSession::from_string("a & b", Game::TESV);
+        let diagnostic =
+            sess.new_error().warning("single ampersand").span(2, 3).label_help("use '&&'").build();
+        assert_eq!("single ampersand", diagnostic.title);
+        assert!(matches!(diagnostic.level, AnnotationType::Warning));
+        assert_eq!((2, 3), diagnostic.span);
+        assert_eq!(Some("use '&&'".to_string()), diagnostic.label);
+        assert!(matches!(diagnostic.label_level, Some(AnnotationType::Help)));
+        assert!(!diagnostic.fatal);
+        assert!(sess.diagnostics().is_empty());
+    }
+
+    #[test]
+    fn render_line_number() {
+        let sess = ParserSession::from_string("a\nb é & c", Game::TESV);
+        let diagnostic = sess.new_error().warning("single ampersand").span(7, 8).build();
+        let rendered = render(&diagnostic, &sess, false);
+        assert!(rendered.contains("2 | b é & c"));
+        assert!(rendered.contains("  |     -"));
+    }
+
+    #[test]
+    fn render_labels() {
+        let sess = ParserSession::from_string("Foo(a, b\nc = 1", Game::TESV);
+        let diagnostic = sess
+            .new_error()
+            .error("expected ')' before end of line")
+            .span(7, 8)
+            .label_error("expected ')' here")
+            .label_at(3, 4, AnnotationType::Info, "to close this")
+            .build();
+        assert_eq!(1, diagnostic.labels.len());
+        let rendered = render(&diagnostic, &sess, false);
+        assert!(rendered.contains("1 | Foo(a, b"));
+        assert!(rendered.contains("info: to close this"));
+        assert!(rendered.contains("^ expected ')' here"));
+    }
+
+    #[test]
+    fn render_multi_line_crlf() {
+        let render_source = |source: &str| {
+            let sess = ParserSession::from_string(source, Game::TESV);
+            let hi = source.find(')').unwrap() + 1;
+            let diagnostic = sess
+                .new_error()
+                .error("bad call")
+                .span(4, hi)
+                .label_error("these arguments")
+                .build();
+            render(&diagnostic, &sess, false)
+        };
+        let rendered = render_source("Foo(a,\r\nbb)\r\nc");
+        assert_eq!(render_source("Foo(a,\nbb)\nc"), rendered);
+        assert!(rendered.contains("2 | | bb)\n  | |___^ these arguments"));
+    }
+
+    #[test]
+    fn render_footer() {
+        let sess = ParserSession::from_string("a & b", Game::TESV);
+        let mut output = Vec::new();
+        sess.new_error()
+            .warning("single ampersand")
+            .span(2, 3)
+            .note("Papyrus has no bitwise operators")
+            .footer_help("use '&&' for a logical and")
+            .emit_to(&mut output)
+            .unwrap();
+        let rendered = String::from_utf8(output).unwrap();
+        assert!(rendered.contains("Papyrus has no bitwise operators"));
+        assert!(rendered.contains("use '&&' for a logical and"));
+        assert_eq!(2, sess.diagnostics()[0].footer.len());
+    }
+
+    #[test]
+    fn render_code() {
+        let sess = ParserSession::from_string("a & b", Game::TESV);
+        let diagnostic = sess
+            .new_error()
+            .warning("single ampersand")
+            .code(codes::SINGLE_AMPERSAND)
+            .span(2, 3)
+            .build();
+        assert_eq!(Some("W0002".to_string()), diagnostic.code);
+        assert!(render(&diagnostic, &sess, false).contains("warning[W0002]: single ampersand"));
+    }
+
+    #[test]
+    fn min_level() {
+        let sess =
+            ParserSession::from_string("a & b", Game::TESV).with_min_level(AnnotationType::Error);
+        sess.new_error().warning("single ampersand").span(2, 3).emit();
+        assert!(sess.diagnostics().is_empty());
+        sess.new_error().error("unknown lexeme").span(2, 3).emit();
+        assert_eq!(1, sess.diagnostics().len());
+
+        let sess =
+            ParserSession::from_string("a & b", Game::TESV).with_min_level(AnnotationType::Error);
+        Lexer::from_sess(&sess).for_each(drop);
+        assert!(sess.diagnostics().is_empty());
+
+        let sess =
+            ParserSession::from_string("a & b", Game::TESV).with_min_level(AnnotationType::Error);
+        let mut out = vec![];
+        sess.new_error().warning("single ampersand").span(2, 3).emit_to(&mut out).unwrap();
+        assert!(out.is_empty());
+        assert!(sess.diagnostics().is_empty());
+        sess.new_error().error("unknown lexeme").span(2, 3).emit_to(&mut out).unwrap();
+        assert!(!out.is_empty());
+        assert_eq!(1, sess.diagnostics().len());
+    }
+
+    #[test]
+    fn min_level_fatal() {
+        let sess =
+            ParserSession::from_string("\"a", Game::TESV).with_min_level(AnnotationType::Error);
+        sess.new_error().fatal("unterminated string").span(0, 2).emit();
+        assert!(sess.has_fatal());
+    }
+
+    #[test]
+    fn diagnostic_render() {
+        let sess = ParserSession::from_string("x = \"abc", Game::TESV);
+        sess.tokenize();
+        let rendered = sess.diagnostics()[0].render(&sess, false);
+        assert!(rendered.contains("error[E0001]: unterminated string"));
+        assert!(rendered.contains("x = \"abc"));
+        assert!(rendered.contains("    ^^^^"));
+    }
+
+    #[test]
+    fn emit_to() {
+        let sess = ParserSession::from_string("a & b", Game::TESV);
+        let mut output = Vec::new();
+        sess.new_error().warning("single ampersand").span(2, 3).emit_to(&mut output).unwrap();
+        let rendered = String::from_utf8(output).unwrap();
+        assert!(rendered.contains("single ampersand"));
+        assert!(rendered.contains("a & b"));
+        assert_eq!(1, sess.diagnostics().len());
     }
 }
@@ -0,0 +1,497 @@
+use crate::ast::{
+    Arg, AssignOp, BinOp, Event, Expr, FnFlag, Function, Group, Member, Param, Property,
+    PropertyFlag, PropertyKind, Script, ScriptFlag, ScriptHeader, State, StateMember, Stmt, Struct,
+    Type, UnOp, VarDecl, VarFlag,
+};
+use crate::lexer::{KwKind, LitKind};
+
+/// Turns an AST back into consistently formatted Papyrus source.
+///
+/// Keywords use their canonical spelling, binary operators are surrounded by spaces and blocks are
+/// indented one level per nesting. Parentheses are only emitted where the AST has them.
+pub struct Formatter {
+    indent: String,
+}
+
+impl Default for Formatter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Formatter {
+    pub fn new() -> Self {
+        Self { indent: "\t".to_string() }
+    }
+
+    /// The string inserted once per nesting level, a tab by default.
+    pub fn set_indent(&mut self, indent: &str) {
+        self.indent = indent.to_string();
+    }
+
+    pub fn format_script(&self, script: &Script) -> String {
+        let mut printer = Printer { indent: &self.indent, level: 0, out: String::new() };
+        printer.script(script);
+        printer.out
+    }
+}
+
+struct Printer<'a> {
+    indent: &'a str,
+    level: usize,
+    out: String,
+}
+
+impl<'a> Printer<'a> {
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.level {
+            self.out.push_str(self.indent);
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn doc(&mut self, doc: &Option<String>) {
+        if let Some(doc) = doc {
+            self.line(&format!("{{{}}}", doc));
+        }
+    }
+
+    fn script(&mut self, script: &Script) {
+        self.header(&script.header);
+        for member in &script.members {
+            self.out.push('\n');
+            self.member(member);
+        }
+    }
+
+    fn header(&mut self, header: &ScriptHeader) {
+        let mut line = format!("{} {}", KwKind::ScriptName.as_str(), header.name);
+        if let Some(extends) = &header.extends {
+            line.push_str(&format!(" {} {}", KwKind::Extends.as_str(), extends));
+        }
+        for flag in &header.flags {
+            line.push(' ');
+            line.push_str(match flag {
+                ScriptFlag::BetaOnly => KwKind::BetaOnly.as_str(),
+                ScriptFlag::Conditional => "Conditional",
+                ScriptFlag::Const => KwKind::Const.as_str(),
+                ScriptFlag::DebugOnly => KwKind::DebugOnly.as_str(),
+                ScriptFlag::Default => "Default",
+                ScriptFlag::Hidden => "Hidden",
+                ScriptFlag::Native => KwKind::Native.as_str(),
+            });
+        }
+        self.line(&line);
+        self.doc(&header.doc);
+    }
+
+    fn member(&mut self, member: &Member) {
+        match member {
+            Member::Import(import) => {
+                self.line(&format!("{} {}", KwKind::Import.as_str(), import.name))
+            }
+            Member::CustomEvent(event) => {
+                self.line(&format!("{} {}", KwKind::CustomEvent.as_str(), event.name))
+            }
+            Member::Property(property) => self.property(property),
+            Member::Group(group) => self.group(group),
+            Member::Function(function) => self.function(function),
+            Member::Event(event) => self.event(event),
+            Member::State(state) => self.state(state),
+            Member::Struct(item) => self.struct_(item),
+            Member::Var(decl) => self.line(&var_decl(decl)),
+        }
+    }
+
+    fn property(&mut self, property: &Property) {
+        let mut line =
+            format!("{} {} {}", ty(&property.ty), KwKind::Property.as_str(), property.name);
+        if let Some(init) = &property.init {
+            line.push_str(&format!(" = {}", expr(init)));
+        }
+        match property.kind {
+            PropertyKind::Full => (),
+            PropertyKind::Auto => line.push_str(&format!(" {}", KwKind::Auto.as_str())),
+            PropertyKind::AutoReadOnly => {
+                line.push_str(&format!(" {}", KwKind::AutoReadOnly.as_str()))
+            }
+        }
+        for flag in &property.flags {
+            line.push_str(match flag {
+                PropertyFlag::Conditional => " Conditional",
+                PropertyFlag::Const => " Const",
+                PropertyFlag::Hidden => " Hidden",
+                PropertyFlag::Mandatory => " Mandatory",
+            });
+        }
+        self.line(&line);
+        self.doc(&property.doc);
+        if property.kind != PropertyKind::Full {
+            return;
+        }
+        self.level += 1;
+        let functions = property.getter.iter().chain(property.setter.iter());
+        for (i, function) in functions.enumerate() {
+            if i > 0 {
+                self.out.push('\n');
+            }
+            self.function(function);
+        }
+        self.level -= 1;
+        self.line(KwKind::EndProperty.as_str());
+    }
+
+    fn group(&mut self, group: &Group) {
+        let mut line = format!("{} {}", KwKind::Group.as_str(), group.name);
+        for flag in &group.flags {
+            line.push(' ');
+            line.push_str(flag);
+        }
+        self.line(&line);
+        self.level += 1;
+        for property in &group.properties {
+            self.property(property);
+        }
+        self.level -= 1;
+        self.line(KwKind::EndGroup.as_str());
+    }
+
+    fn function(&mut self, function: &Function) {
+        let mut line = String::new();
+        if let Some(return_type) = &function.return_type {
+            line.push_str(&ty(return_type));
+            line.push(' ');
+        }
+        line.push_str(&format!(
+            "{} {}({})",
+            KwKind::Function.as_str(),
+            function.name,
+            params(&function.params)
+        ));
+        for flag in &function.flags {
+            line.push(' ');
+            line.push_str(match flag {
+                FnFlag::BetaOnly => KwKind::BetaOnly.as_str(),
+                FnFlag::DebugOnly => KwKind::DebugOnly.as_str(),
+                FnFlag::Global => KwKind::Global.as_str(),
+                FnFlag::Native => KwKind::Native.as_str(),
+            });
+        }
+        self.line(&line);
+        self.doc(&function.doc);
+        if !function.flags.contains(&FnFlag::Native) {
+            self.block(&function.body);
+            self.line(KwKind::EndFunction.as_str());
+        }
+    }
+
+    fn event(&mut self, event: &Event) {
+        let mut line =
+            format!("{} {}({})", KwKind::Event.as_str(), event.name, params(&event.params));
+        if event.is_native {
+            line.push_str(&format!(" {}", KwKind::Native.as_str()));
+        }
+        self.line(&line);
+        self.doc(&event.doc);
+        if event.is_native {
+            return;
+        }
+        self.block(&event.body);
+        self.line(KwKind::EndEvent.as_str());
+    }
+
+    fn state(&mut self, state: &State) {
+        let mut line = String::new();
+        if state.is_auto {
+            line.push_str(KwKind::Auto.as_str());
+            line.push(' ');
+        }
+        line.push_str(&format!("{} {}", KwKind::State.as_str(), state.name));
+        self.line(&line);
+        self.level += 1;
+        for (i, member) in state.members.iter().enumerate() {
+            if i > 0 {
+                self.out.push('\n');
+            }
+            match member {
+                StateMember::Function(function) => self.function(function),
+                StateMember::Event(event) => self.event(event),
+            }
+        }
+        self.level -= 1;
+        self.line(KwKind::EndState.as_str());
+    }
+
+    fn struct_(&mut self, item: &Struct) {
+        self.line(&format!("{} {}", KwKind::Struct.as_str(), item.name));
+        self.level += 1;
+        for field in &item.fields {
+            self.line(&var_decl(field));
+        }
+        self.level -= 1;
+        self.line(KwKind::EndStruct.as_str());
+    }
+
+    fn block(&mut self, stmts: &[Stmt]) {
+        self.level += 1;
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+        self.level -= 1;
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Var(decl) => self.line(&var_decl(decl)),
+            Stmt::Assign { target, op, value, .. } => {
+                let op = match op {
+                    AssignOp::Assign => "=",
+                    AssignOp::Add => "+=",
+                    AssignOp::Sub => "-=",
+                    AssignOp::Mul => "*=",
+                    AssignOp::Div => "/=",
+                    AssignOp::Mod => "%=",
+                };
+                self.line(&format!("{} {} {}", expr(target), op, expr(value)));
+            }
+            Stmt::Return { value: Some(value), .. } => {
+                self.line(&format!("{} {}", KwKind::Return.as_str(), expr(value)))
+            }
+            Stmt::Return { value: None, .. } => self.line(KwKind::Return.as_str()),
+            Stmt::If { arms, else_block, .. } => {
+                for (i, arm) in arms.iter().enumerate() {
+                    let kw = if i == 0 { KwKind::If } else { KwKind::ElseIf };
+                    self.line(&format!("{} {}", kw.as_str(), expr(&arm.cond)));
+                    self.block(&arm.body);
+                }
+                if let Some(else_block) = else_block {
+                    self.line(KwKind::Else.as_str());
+                    self.block(else_block);
+                }
+                self.line(KwKind::EndIf.as_str());
+            }
+            Stmt::While { cond, body, .. } => {
+                self.line(&format!("{} {}", KwKind::While.as_str(), expr(cond)));
+                self.block(body);
+                self.line(KwKind::EndWhile.as_str());
+            }
+            Stmt::Expr(e) => self.line(&expr(e)),
+        }
+    }
+}
+
+fn ty(ty_: &Type) -> String {
+    match ty_ {
+        Type::Bool => KwKind::Bool.as_str().to_string(),
+        Type::Float => KwKind::Float.as_str().to_string(),
+        Type::Int => KwKind::Int.as_str().to_string(),
+        Type::String => KwKind::String.as_str().to_string(),
+        Type::Var => KwKind::Var.as_str().to_string(),
+        Type::Object(name) => name.clone(),
+        Type::Array(elem) => format!("{}[]", ty(elem)),
+        Type::CustomEventName => KwKind::CustomEventName.as_str().to_string(),
+        Type::ScriptEventName => KwKind::ScriptEventName.as_str().to_string(),
+    }
+}
+
+fn params(params: &[Param]) -> String {
+    let params = params.iter().map(|param| match &param.default {
+        Some(default) => format!("{} {} = {}", ty(&param.ty), param.name, expr(default)),
+        None => format!("{} {}", ty(&param.ty), param.name),
+    });
+    params.collect::<Vec<_>>().join(", ")
+}
+
+fn var_decl(decl: &VarDecl) -> String {
+    let mut line = format!("{} {}", ty(&decl.ty), decl.name);
+    if let Some(init) = &decl.init {
+        line.push_str(&format!(" = {}", expr(init)));
+    }
+    for flag in &decl.flags {
+        line.push_str(match flag {
+            VarFlag::Conditional => " Conditional",
+            VarFlag::Const => " Const",
+        });
+    }
+    line
+}
+
+fn literal(lit: &LitKind) -> String {
+    match lit {
+        LitKind::Bool(true) => "True".to_string(),
+        LitKind::Bool(false) => "False".to_string(),
+        LitKind::None => "None".to_string(),
+        LitKind::Str(value) => {
+            let mut out = String::from("\"");
+            for c in value.chars() {
+                match c {
+                    '"' => out.push_str("\\\""),
+                    '\\' => out.push_str("\\\\"),
+                    '\n' => out.push_str("\\n"),
+                    '\r' => out.push_str("\\r"),
+                    '\t' => out.push_str("\\t"),
+                    c => out.push(c),
+                }
+            }
+            out.push('"');
+            out
+        }
+        LitKind::Float(_, raw) => raw.clone(),
+        LitKind::Integer(value, true) => format!("0x{:X}", *value as u32),
+        LitKind::Integer(value, false) => value.to_string(),
+    }
+}
+
+fn args(args: &[Arg]) -> String {
+    let args = args.iter().map(|arg| match &arg.name {
+        Some(name) => format!("{} = {}", name, expr(&arg.value)),
+        None => expr(&arg.value),
+    });
+    args.collect::<Vec<_>>().join(", ")
+}
+
+fn expr(e: &Expr) -> String {
+    match e {
+        Expr::Literal { lit, .. } => literal(lit),
+        Expr::Ident { name, .. } => name.clone(),
+        Expr::Unary { op: UnOp::Neg, expr: operand, .. } => format!("-{}", expr(operand)),
+        Expr::Unary { op: UnOp::Not, expr: operand, .. } => format!("!{}", expr(operand)),
+        Expr::Binary { op, lhs, rhs, .. } => {
+            let op = match op {
+                BinOp::Or => "||",
+                BinOp::And => "&&",
+                BinOp::Eq => "==",
+                BinOp::Ne => "!=",
+                BinOp::Lt => "<",
+                BinOp::Le => "<=",
+                BinOp::Gt => ">",
+                BinOp::Ge => ">=",
+                BinOp::Add => "+",
+                BinOp::Sub => "-",
+                BinOp::Mul => "*",
+                BinOp::Div => "/",
+                BinOp::Mod => "%",
+            };
+            format!("{} {} {}", expr(lhs), op, expr(rhs))
+        }
+        Expr::Call { callee, args: call_args, .. } => {
+            format!("{}({})", expr(callee), args(call_args))
+        }
+        Expr::Member { base, field, .. } => format!("{}.{}", expr(base), field),
+        Expr::Index { base, index, .. } => format!("{}[{}]", expr(base), expr(index)),
+        Expr::Cast { expr: operand, ty: ty_, .. } => {
+            format!("{} {} {}", expr(operand), KwKind::As.as_str(), ty(ty_))
+        }
+        Expr::TypeCheck { expr: operand, ty: ty_, .. } => {
+            format!("{} {} {}", expr(operand), KwKind::Is.as_str(), ty(ty_))
+        }
+        Expr::Paren { expr: inner, .. } => format!("({})", expr(inner)),
+        Expr::NewArray { ty: ty_, size, .. } => {
+            format!("{} {}[{}]", KwKind::New.as_str(), ty(ty_), expr(size))
+        }
+        Expr::NewStruct { name, .. } => format!("{} {}", KwKind::New.as_str(), name),
+        Expr::SelfRef { .. } => KwKind::_Self.as_str().to_string(),
+        Expr::ParentRef { .. } => KwKind::Parent.as_str().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Game, Parser, ParserSession};
+
+    fn parse(script: &str, game: Game) -> Script {
+        let sess = ParserSession::from_string(script, game);
+        let script = Parser::from_sess(&sess).parse_script().unwrap();
+        assert!(sess.diagnostics().is_empty());
+        script
+    }
+
+    #[test]
+    fn round_trip() {
+        let source = "ScriptName Counter Extends Quest Hidden\n\
+                      { A counter }\n\
+                      \n\
+                      Import Utility\n\
+                      \n\
+                      Int Property Count = 0x1F Auto\n\
+                      \n\
+                      Float Property Speed\n\
+                      \tFloat Function Get()\n\
+                      \t\tReturn mSpeed * 2.5\n\
+                      \tEndFunction\n\
+                      EndProperty\n\
+                      \n\
+                      Float mSpeed = 1.0\n\
+                      \n\
+                      Int Function Next(Int step = 1) Global\n\
+                      { Steps the counter }\n\
+                      \tInt[] values = New Int[10]\n\
+                      \tIf step > 0 && !(step == 5)\n\
+                      \t\tvalues[0] += step\n\
+                      \tElseIf step < 0\n\
+                      \t\tDebug.Trace(\"negative \\\"step\\\"\", 2)\n\
+                      \tElse\n\
+                      \t\tReturn\n\
+                      \tEndIf\n\
+                      \tWhile step\n\
+                      \t\tstep -= (GetRef() As Actor).GetLevel()\n\
+                      \tEndWhile\n\
+                      \tReturn values[0]\n\
+                      EndFunction\n\
+                      \n\
+                      Auto State Waiting\n\
+                      \tEvent OnInit()\n\
+                      \tEndEvent\n\
+                      \n\
+                      \tFunction Foo() Native\n\
+                      EndState\n";
+        let script = parse(source, Game::TESV);
+        let formatted = Formatter::new().format_script(&script);
+        assert_eq!(source, formatted);
+        assert_eq!(script, parse(&formatted, Game::TESV));
+    }
+
+    #[test]
+    fn float_notation() {
+        let source = "ScriptName Foo\n\
+                      \n\
+                      Float x = 1.50\n\
+                      \n\
+                      Float y = 2E-4\n";
+        assert_eq!(source, Formatter::new().format_script(&parse(source, Game::TESV)));
+    }
+
+    #[test]
+    fn normalizes() {
+        let source = "scriptname foo const default\n\
+                      struct Point\n\
+                      float x=1.5   const\n\
+                      endstruct\n\
+                      Group Stats CollapsedOnRef\n\
+                      int property Health auto const mandatory\n\
+                      endgroup\n\
+                      function bar( int a,int b )\n\
+                      Foo( x = a+b )\n\
+                      endfunction";
+        let formatted = Formatter::new().format_script(&parse(source, Game::FO4));
+        let expected = "ScriptName foo Const Default\n\
+                        \n\
+                        Struct Point\n\
+                        \x20 Float x = 1.5 Const\n\
+                        EndStruct\n\
+                        \n\
+                        Group Stats CollapsedOnRef\n\
+                        \x20 Int Property Health Auto Const Mandatory\n\
+                        EndGroup\n\
+                        \n\
+                        Function bar(Int a, Int b)\n\
+                        \x20 Foo(x = a + b)\n\
+                        EndFunction\n";
+        let mut formatter = Formatter::new();
+        formatter.set_indent("  ");
+        assert_eq!(expected, formatter.format_script(&parse(source, Game::FO4)));
+        assert_eq!(formatted, Formatter::new().format_script(&parse(&formatted, Game::FO4)));
+    }
+}
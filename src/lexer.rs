@@ -1,41 +1,92 @@
-use std::str::{Bytes, FromStr};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, BufRead};
+use std::mem;
+use std::slice;
+use std::str::FromStr;
 
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use strum_macros::EnumString;
 
+use crate::errors::{codes, ErrorBuilder};
 use crate::{Game, ParserSession};
 
 const EOF_CHAR: u8 = b'\0';
 
 fn is_whitespace(byte: u8) -> bool {
-    match byte {
-        b' ' | b'\t' => true,
-        _ => false,
-    }
+    matches!(byte, b' ' | b'\t')
 }
 
 fn is_newline_start(byte: u8) -> bool {
-    match byte {
-        b'\r' | b'\n' => true,
-        _ => false,
-    }
+    matches!(byte, b'\r' | b'\n')
 }
 
 fn is_id_start(byte: u8) -> bool {
-    (b'a' <= byte && byte <= b'z') || (b'A' <= byte && byte <= b'Z') || byte == b'_'
+    byte.is_ascii_alphabetic() || byte == b'_'
 }
 
-fn is_id_continue(byte: u8, game: &Game) -> bool {
-    is_id_start(byte) || (b'0' <= byte && byte <= b'9') || (*game == Game::FO4 && byte == b':')
+fn is_id_continue(byte: u8) -> bool {
+    is_id_start(byte) || byte.is_ascii_digit()
 }
 
-#[derive(PartialEq, Debug)]
+/// Splits a Fallout 4 namespaced identifier such as `Foo:Bar:Baz` into its parts.
+pub fn split_namespace(ident: &str) -> Vec<&str> {
+    ident.split(':').collect()
+}
+
+/// Lexes `src` without keeping a session around, for when diagnostics are not needed.
+///
+/// Same as `ParserSession::tokenize`, but the diagnostics are discarded.
+///
+/// ```
+/// use libpapyrus::{tokenize_str, Game, TokenKind};
+///
+/// let tokens = tokenize_str("Int x", Game::TESV);
+/// assert_eq!(tokens.last().unwrap().kind, TokenKind::Eof);
+/// ```
+pub fn tokenize_str(src: &str, game: Game) -> Vec<Token> {
+    ParserSession::from_string(src, game).tokenize()
+}
+
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum LitKind {
+    /// `True` or `False`, which are lexed as literals rather than keywords.
+    Bool(bool),
+    /// The `None` object, also lexed as a literal.
+    None,
     Str(String),
-    Float(f32),
+    /// The value and the text it was written as, so `1.50` and `1.5e0` can be told apart.
+    ///
+    /// Only the value is serialized, the raw text is rebuilt from it when deserializing.
+    #[cfg_attr(
+        feature = "serde",
+        serde(serialize_with = "serialize_float", deserialize_with = "deserialize_float")
+    )]
+    Float(f32, /* raw */ String),
     Integer(i32, /* is_hex */ bool),
 }
 
-#[derive(EnumString, PartialEq, Debug)]
+#[cfg(feature = "serde")]
+fn serialize_float<S: serde::Serializer>(
+    value: &f32,
+    _raw: &str,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f32(*value)
+}
+
+#[cfg(feature = "serde")]
+fn deserialize_float<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<(f32, String), D::Error> {
+    let value = f32::deserialize(deserializer)?;
+    Ok((value, format!("{:?}", value)))
+}
+
+#[derive(EnumString, PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[strum(serialize_all = "lowercase")]
 pub enum KwKind {
     As,
@@ -90,11 +141,138 @@ pub enum KwKind {
     Var,
 }
 
-#[derive(PartialEq, Debug)]
+impl KwKind {
+    /// Looks up the keyword `ident` spells in `game`, ignoring case.
+    ///
+    /// Keywords added in Fallout 4 are plain identifiers in Skyrim scripts.
+    pub fn from_ident(ident: &str, game: Game) -> Option<KwKind> {
+        let kw = KwKind::from_str(&ident.to_ascii_lowercase()).ok()?;
+        if game != Game::FO4 && kw.is_fo4_only() {
+            None
+        } else {
+            Some(kw)
+        }
+    }
+
+    /// Whether this keyword only exists in Fallout 4.
+    pub fn is_fo4_only(&self) -> bool {
+        matches!(
+            self,
+            KwKind::BetaOnly
+                | KwKind::Const
+                | KwKind::CustomEvent
+                | KwKind::CustomEventName
+                | KwKind::DebugOnly
+                | KwKind::EndGroup
+                | KwKind::EndStruct
+                | KwKind::Group
+                | KwKind::Is
+                | KwKind::ScriptEventName
+                | KwKind::Struct
+                | KwKind::StructVarName
+                | KwKind::Var
+        )
+    }
+
+    /// Whether this keyword names a built-in type, such as `Int` or `Var`.
+    pub fn is_type(&self) -> bool {
+        matches!(
+            self,
+            KwKind::Bool
+                | KwKind::Float
+                | KwKind::Int
+                | KwKind::String
+                | KwKind::Var
+                | KwKind::CustomEventName
+                | KwKind::ScriptEventName
+        )
+    }
+
+    /// Whether this keyword is a flag following a declaration, such as `Native` or `Auto`.
+    ///
+    /// Flags like `Hidden` and `Conditional` are not keywords and are lexed as identifiers.
+    pub fn is_modifier(&self) -> bool {
+        matches!(
+            self,
+            KwKind::Auto
+                | KwKind::AutoReadOnly
+                | KwKind::Global
+                | KwKind::Native
+                | KwKind::BetaOnly
+                | KwKind::Const
+                | KwKind::DebugOnly
+        )
+    }
+
+    /// The conventional PascalCase spelling of this keyword.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KwKind::As => "As",
+            KwKind::Auto => "Auto",
+            KwKind::AutoReadOnly => "AutoReadOnly",
+            KwKind::Bool => "Bool",
+            KwKind::Else => "Else",
+            KwKind::ElseIf => "ElseIf",
+            KwKind::EndEvent => "EndEvent",
+            KwKind::EndFunction => "EndFunction",
+            KwKind::EndIf => "EndIf",
+            KwKind::EndProperty => "EndProperty",
+            KwKind::EndState => "EndState",
+            KwKind::EndWhile => "EndWhile",
+            KwKind::Event => "Event",
+            KwKind::Extends => "Extends",
+            KwKind::False => "False",
+            KwKind::Float => "Float",
+            KwKind::Function => "Function",
+            KwKind::Global => "Global",
+            KwKind::If => "If",
+            KwKind::Import => "Import",
+            KwKind::Int => "Int",
+            KwKind::Length => "Length",
+            KwKind::Native => "Native",
+            KwKind::New => "New",
+            KwKind::None => "None",
+            KwKind::Parent => "Parent",
+            KwKind::Property => "Property",
+            KwKind::Return => "Return",
+            KwKind::ScriptName => "ScriptName",
+            KwKind::_Self => "Self",
+            KwKind::State => "State",
+            KwKind::String => "String",
+            KwKind::True => "True",
+            KwKind::While => "While",
+            KwKind::BetaOnly => "BetaOnly",
+            KwKind::Const => "Const",
+            KwKind::CustomEvent => "CustomEvent",
+            KwKind::CustomEventName => "CustomEventName",
+            KwKind::DebugOnly => "DebugOnly",
+            KwKind::EndGroup => "EndGroup",
+            KwKind::EndStruct => "EndStruct",
+            KwKind::Group => "Group",
+            KwKind::Is => "Is",
+            KwKind::ScriptEventName => "ScriptEventName",
+            KwKind::Struct => "Struct",
+            KwKind::StructVarName => "StructVarName",
+            KwKind::Var => "Var",
+        }
+    }
+}
+
+impl fmt::Display for KwKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum TokenKind {
     Eof,
+    Unknown,
     Whitespace,
     Newline(/* is_crlf */ bool),
+    /// A `\` and the line break right after it, which joins the next line to the current one.
+    LineContinuation,
     Doc(String),
     Comment(String),
     Literal(LitKind),
@@ -107,6 +285,8 @@ pub enum TokenKind {
     RSquare,
     Dot,
     Comma,
+    /// A `:` that does not separate the parts of a Fallout 4 namespaced identifier.
+    Colon,
 
     Minus,
     MinusEq,
@@ -131,7 +311,73 @@ pub enum TokenKind {
     Or,
 }
 
-#[derive(PartialEq, Debug)]
+impl TokenKind {
+    /// Whether both kinds are the same ignoring their payloads, e.g. any two identifiers.
+    ///
+    /// Literals must still be of the same `LitKind` variant and keywords the same keyword.
+    pub fn discriminant_eq(&self, other: &TokenKind) -> bool {
+        match (self, other) {
+            (TokenKind::Literal(a), TokenKind::Literal(b)) => {
+                mem::discriminant(a) == mem::discriminant(b)
+            }
+            (TokenKind::Keyword(a), TokenKind::Keyword(b)) => a == b,
+            _ => mem::discriminant(self) == mem::discriminant(other),
+        }
+    }
+}
+
+/// Displays operators and punctuation as spelled in source, everything else as a category.
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            TokenKind::Eof => "end of file",
+            TokenKind::Unknown => "unknown token",
+            TokenKind::Whitespace => "whitespace",
+            TokenKind::Newline(_) => "newline",
+            TokenKind::LineContinuation => "line continuation",
+            TokenKind::Doc(_) => "documentation",
+            TokenKind::Comment(_) => "comment",
+            TokenKind::Literal(LitKind::Bool(_)) => "bool literal",
+            TokenKind::Literal(LitKind::None) => "None",
+            TokenKind::Literal(LitKind::Str(_)) => "string literal",
+            TokenKind::Literal(LitKind::Float(..)) => "float literal",
+            TokenKind::Literal(LitKind::Integer(..)) => "integer literal",
+            TokenKind::Ident(_) => "identifier",
+            TokenKind::Keyword(kw) => return write!(f, "{}", kw),
+            TokenKind::LParen => "(",
+            TokenKind::RParen => ")",
+            TokenKind::LSquare => "[",
+            TokenKind::RSquare => "]",
+            TokenKind::Dot => ".",
+            TokenKind::Comma => ",",
+            TokenKind::Colon => ":",
+            TokenKind::Minus => "-",
+            TokenKind::MinusEq => "-=",
+            TokenKind::Plus => "+",
+            TokenKind::PlusEq => "+=",
+            TokenKind::Equal => "=",
+            TokenKind::Not => "!",
+            TokenKind::Multiply => "*",
+            TokenKind::MultiplyEq => "*=",
+            TokenKind::Divide => "/",
+            TokenKind::DivideEq => "/=",
+            TokenKind::Modulo => "%",
+            TokenKind::ModuleEq => "%=",
+            TokenKind::CmpEQ => "==",
+            TokenKind::CmpNE => "!=",
+            TokenKind::CmpLT => "<",
+            TokenKind::CmpLE => "<=",
+            TokenKind::CmpGT => ">",
+            TokenKind::CmpGE => ">=",
+            TokenKind::And => "&&",
+            TokenKind::Or => "||",
+        };
+        f.write_str(s)
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Token {
     pub kind: TokenKind,
     lo: usize,
@@ -145,34 +391,214 @@ impl Token {
         };
         Token { kind, lo, hi }
     }
+
+    /// Byte offset of the first byte of this token.
+    pub fn lo(&self) -> usize {
+        self.lo
+    }
+
+    /// Byte offset one past the last byte of this token.
+    pub fn hi(&self) -> usize {
+        self.hi
+    }
+
+    /// Byte range `(lo, hi)` this token covers in the source.
+    pub fn span(&self) -> (usize, usize) {
+        (self.lo, self.hi)
+    }
+
+    /// Whether this token is whitespace, a newline, a comment or documentation.
+    pub fn is_trivia(&self) -> bool {
+        matches!(
+            self.kind,
+            TokenKind::Whitespace
+                | TokenKind::Newline(_)
+                | TokenKind::LineContinuation
+                | TokenKind::Comment(_)
+                | TokenKind::Doc(_)
+        )
+    }
+}
+
+enum Input<'a> {
+    Source(slice::Iter<'a, u8>),
+    Reader {
+        bytes: Box<dyn Iterator<Item = io::Result<u8>> + 'a>,
+        // reports a failed read, which ends the input
+        sess: &'a ParserSession,
+        read: usize,
+    },
+}
+
+impl Iterator for Input<'_> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        match self {
+            Input::Source(bytes) => bytes.next().copied(),
+            Input::Reader { bytes, sess, read } => match bytes.next()? {
+                Ok(byte) => {
+                    *read += 1;
+                    Some(byte)
+                }
+                Err(err) => {
+                    sess.new_error()
+                        .fatal(&format!("could not read script: {}", err))
+                        .code(codes::READ_FAILED)
+                        .span(*read, *read)
+                        .emit();
+                    *bytes = Box::new(std::iter::empty());
+                    None
+                }
+            },
+        }
+    }
+}
+
+/// A position of a `Lexer` to go back to, see `Lexer::checkpoint`.
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    pos: usize,
+    finished: bool,
+    newline_skipped: bool,
+    doc_skipped: Option<Token>,
 }
 
 pub struct Lexer<'a> {
     sess: &'a ParserSession,
-    initial_len: usize,
-    bytes: Bytes<'a>,
+    bytes: Input<'a>,
+    // the two bytes after `pos`, read ahead so the input is only consumed once
+    next: Option<u8>,
+    after_next: Option<u8>,
+    // start of the current logical line, for `max_line_len`
+    line_lo: usize,
+    pos: usize,
+    // first line break consumed by the current token
+    token_newline: Option<usize>,
+    finished: bool,
+    recover: bool,
+    // trivia skipped by the last call to next_significant_token
+    newline_skipped: bool,
+    doc_skipped: Option<Token>,
 }
 
 impl<'a> Lexer<'a> {
     pub fn from_sess(sess: &'a ParserSession) -> Self {
-        let bytes = sess.src.content.bytes();
-        Self { initial_len: bytes.len(), sess, bytes }
+        Self::new(sess, Input::Source(sess.src.content.as_bytes().iter()))
+    }
+
+    /// Lexes `reader` incrementally instead of the session source, which is never loaded.
+    ///
+    /// `sess` only provides the game and collects diagnostics, so it is usually created from an
+    /// empty string. Diagnostics cannot be rendered without the source and `text` returns empty
+    /// strings. An I/O error is reported as a fatal diagnostic and ends the input.
+    pub fn from_reader<R: BufRead + 'a>(sess: &'a ParserSession, reader: R) -> Self {
+        Self::new(sess, Input::Reader { bytes: Box::new(reader.bytes()), sess, read: 0 })
+    }
+
+    fn new(sess: &'a ParserSession, mut bytes: Input<'a>) -> Self {
+        Self {
+            sess,
+            next: bytes.next(),
+            after_next: bytes.next(),
+            line_lo: 0,
+            bytes,
+            pos: 0,
+            token_newline: None,
+            finished: false,
+            recover: false,
+            newline_skipped: false,
+            doc_skipped: None,
+        }
+    }
+
+    /// Whether to keep lexing after an error in a literal, comment or documentation block.
+    ///
+    /// By default such errors are fatal and the rest of the source is skipped. When recovering
+    /// they are reported as regular errors and the failed lexeme becomes a `TokenKind::Unknown`.
+    pub fn set_recover(&mut self, recover: bool) {
+        self.recover = recover;
+    }
+
+    /// Saves the current position so lexing can resume from it with `restore`.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            pos: self.pos,
+            finished: self.finished,
+            newline_skipped: self.newline_skipped,
+            doc_skipped: self.doc_skipped.clone(),
+        }
+    }
+
+    /// Goes back to `checkpoint`, which must come from a lexer over the same session.
+    ///
+    /// Diagnostics emitted since the checkpoint are kept.
+    ///
+    /// # Panics
+    ///
+    /// Panics for lexers created with `from_reader`, which cannot rewind their input.
+    pub fn restore(&mut self, checkpoint: Checkpoint) {
+        if let Input::Reader { .. } = self.bytes {
+            panic!("cannot restore a lexer reading from a reader");
+        }
+        let mut bytes = self.sess.src.content.as_bytes()[checkpoint.pos..].iter();
+        self.next = bytes.next().copied();
+        self.after_next = bytes.next().copied();
+        self.bytes = Input::Source(bytes);
+        self.pos = checkpoint.pos;
+        let line = self.sess.src.lineno_from_offset(checkpoint.pos, 1).0;
+        self.line_lo = self.sess.src.line_starts[line - 1];
+        self.finished = checkpoint.finished;
+        self.newline_skipped = checkpoint.newline_skipped;
+        self.doc_skipped = checkpoint.doc_skipped;
+    }
+
+    /// Goes back to the start of the source, see `restore`.
+    pub fn reset(&mut self) {
+        self.restore(Checkpoint {
+            pos: 0,
+            finished: false,
+            newline_skipped: false,
+            doc_skipped: None,
+        });
+    }
+
+    /// The source text of `token`, as spelled by the user.
+    ///
+    /// Keywords are matched ignoring case, so this is how to recover the original casing of a
+    /// `TokenKind::Keyword`.
+    pub fn text(&self, token: &Token) -> &'a str {
+        self.sess.src.content.get(token.lo()..token.hi()).unwrap_or_default()
     }
 
     fn cur_pos(&self) -> usize {
-        self.initial_len - self.bytes.len()
+        self.pos
     }
 
     fn is_eof(&self) -> bool {
-        self.bytes.len() == 0
+        self.next.is_none()
     }
 
     fn peek_byte(&self) -> u8 {
-        self.bytes.clone().nth(0).unwrap_or(EOF_CHAR)
+        self.next.unwrap_or(EOF_CHAR)
+    }
+
+    fn peek_second_byte(&self) -> u8 {
+        self.after_next.unwrap_or(EOF_CHAR)
     }
 
     fn next_byte(&mut self) -> Option<u8> {
-        self.bytes.next()
+        let b = self.next?;
+        if is_newline_start(b) && self.token_newline.is_none() {
+            self.token_newline = Some(self.pos);
+        }
+        self.next = self.after_next;
+        self.after_next = self.bytes.next();
+        self.pos += 1;
+        if b == b'\n' {
+            self.line_lo = self.pos;
+        }
+        Some(b)
     }
 
     fn has_equal_next(&mut self, yes: TokenKind, no: TokenKind) -> TokenKind {
@@ -184,20 +610,70 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    /// Lexes the next token that is not whitespace, a newline, a comment or documentation.
+    ///
+    /// Statements end at newlines, so whether one was skipped is kept in `newline_skipped`.
+    pub fn next_significant_token(&mut self) -> Token {
+        self.newline_skipped = false;
+        self.doc_skipped = None;
+        loop {
+            let token = self.next_token();
+            match token.kind {
+                TokenKind::Newline(_) => self.newline_skipped = true,
+                TokenKind::Whitespace | TokenKind::LineContinuation | TokenKind::Comment(_) => (),
+                TokenKind::Doc(_) => self.doc_skipped = Some(token),
+                _ => return token,
+            }
+        }
+    }
+
+    /// Whether the last `next_significant_token` call skipped a newline.
+    pub fn newline_skipped(&self) -> bool {
+        self.newline_skipped
+    }
+
+    /// The last documentation block skipped by the last `next_significant_token` call.
+    pub fn doc_skipped(&self) -> Option<&Token> {
+        self.doc_skipped.as_ref()
+    }
+
+    /// Lexes the next token.
+    ///
+    /// After a fatal diagnostic the rest of the source is skipped and `Eof` is returned.
     pub fn next_token(&mut self) -> Token {
         let start_pos = self.cur_pos();
+        let line_lo = self.line_lo;
+        self.token_newline = None;
         let first_byte = self.next_byte().unwrap_or(EOF_CHAR);
         let token_kind = match first_byte {
-            EOF_CHAR => TokenKind::Eof,
+            EOF_CHAR => {
+                if !self.sess.has_fatal() {
+                    self.check_line_len(line_lo, start_pos);
+                }
+                self.line_lo = start_pos;
+                TokenKind::Eof
+            }
             b if is_whitespace(b) => self.whitespace(),
-            b if is_newline_start(b) => self.newline(first_byte),
-            b'{' => self.documentation(),
+            b if is_newline_start(b) => {
+                self.check_line_len(line_lo, start_pos);
+                let kind = self.newline(first_byte);
+                self.line_lo = self.cur_pos();
+                kind
+            }
+            b'\\' if is_newline_start(self.peek_byte()) => {
+                // the next line is part of this one
+                let first_byte = self.next_byte().unwrap();
+                self.newline(first_byte);
+                self.line_lo = line_lo;
+                TokenKind::LineContinuation
+            }
+            b'{' => self.documentation(start_pos),
             b';' => match self.peek_byte() {
-                b'/' => self.block_comment(),
+                b'/' => self.block_comment(start_pos),
                 _ => self.line_comment(),
             },
-            b'"' => TokenKind::Literal(self.string()),
-            b @ b'0'..=b'9' => TokenKind::Literal(self.number(b)),
+            b'"' => self.string(start_pos),
+            b @ b'0'..=b'9' => self.number(b),
             b if is_id_start(b) => self.ident(b),
             b'(' => TokenKind::LParen,
             b')' => TokenKind::RParen,
@@ -205,14 +681,9 @@ impl<'a> Lexer<'a> {
             b']' => TokenKind::RSquare,
             b'.' => TokenKind::Dot,
             b',' => TokenKind::Comma,
-            b'-' => match self.peek_byte() {
-                b'=' => {
-                    self.next_byte();
-                    TokenKind::MinusEq
-                }
-                b @ b'0'..=b'9' => TokenKind::Literal(self.number(b)),
-                _ => TokenKind::Minus,
-            },
+            b':' => TokenKind::Colon,
+            // a '-' is never part of a number, the parser decides if it negates or subtracts
+            b'-' => self.has_equal_next(TokenKind::MinusEq, TokenKind::Minus),
             b'+' => self.has_equal_next(TokenKind::PlusEq, TokenKind::Plus),
             b'=' => self.has_equal_next(TokenKind::CmpEQ, TokenKind::Equal),
             b'!' => self.has_equal_next(TokenKind::CmpNE, TokenKind::Not),
@@ -230,6 +701,7 @@ impl<'a> Lexer<'a> {
                     self.sess
                         .new_error()
                         .warning("expected second '&' for binary AND")
+                        .code(codes::SINGLE_AMPERSAND)
                         .span(start_pos, self.cur_pos())
                         .label_help("try using '&&' instead")
                         .emit();
@@ -245,6 +717,7 @@ impl<'a> Lexer<'a> {
                     self.sess
                         .new_error()
                         .warning("expected second '|' for binary OR")
+                        .code(codes::SINGLE_PIPE)
                         .span(start_pos, self.cur_pos())
                         .label_help("try using '||' instead")
                         .emit();
@@ -252,18 +725,67 @@ impl<'a> Lexer<'a> {
                 }
             },
             _ => {
+                // skip the rest of a multi-byte character so it is reported only once
+                while self.peek_byte() & 0xC0 == 0x80 {
+                    self.next_byte();
+                }
                 self.sess
                     .new_error()
-                    .fatal("unknown lexeme")
+                    .error("unknown lexeme")
+                    .code(codes::UNKNOWN_LEXEME)
                     .span(start_pos, self.cur_pos())
                     .label_help("are you using unicode characters for an identifier?")
                     .emit();
-                unreachable!()
+                TokenKind::Unknown
             }
         };
         Token::new(token_kind, start_pos, self.cur_pos())
     }
 
+    // warns if the line `lo..hi` is longer than the session allows
+    fn check_line_len(&self, lo: usize, hi: usize) {
+        match self.sess.max_line_len {
+            Some(max) if hi - lo > max => self
+                .sess
+                .new_error()
+                .warning(&format!("line is longer than {} bytes", max))
+                .code(codes::LINE_TOO_LONG)
+                .span(lo, hi)
+                .label_warning("the game compiler may reject this line")
+                .emit(),
+            _ => (),
+        }
+    }
+
+    // stops lexing after a fatal error, the rest of the source is skipped
+    fn abort(&mut self) -> TokenKind {
+        let skipped = self.next.take().map_or(0, |_| 1)
+            + self.after_next.take().map_or(0, |_| 1)
+            + self.bytes.by_ref().count();
+        self.pos += skipped;
+        TokenKind::Eof
+    }
+
+    // lexing errors are fatal unless recovering
+    fn report(&self, title: &str) -> ErrorBuilder<'a> {
+        let mut builder = self.sess.new_error();
+        if self.recover {
+            builder.error(title);
+        } else {
+            builder.fatal(title);
+        }
+        builder
+    }
+
+    // token for a lexeme that failed to lex
+    fn fail(&mut self) -> TokenKind {
+        if self.recover {
+            TokenKind::Unknown
+        } else {
+            self.abort()
+        }
+    }
+
     fn whitespace(&mut self) -> TokenKind {
         while is_whitespace(self.peek_byte()) {
             self.next_byte();
@@ -279,8 +801,14 @@ impl<'a> Lexer<'a> {
         TokenKind::Newline(is_crlf)
     }
 
-    fn documentation(&mut self) -> TokenKind {
-        let mut value = String::new();
+    // end of the line the current token starts on, excluding the line break
+    fn line_end(&self) -> usize {
+        self.token_newline.unwrap_or(self.cur_pos())
+    }
+
+    fn documentation(&mut self, start_pos: usize) -> TokenKind {
+        let value_lo = self.cur_pos();
+        let mut value = vec![];
         let mut terminated = false;
         while let Some(b) = self.next_byte() {
             match b {
@@ -288,93 +816,146 @@ impl<'a> Lexer<'a> {
                     terminated = true;
                     break;
                 }
-                _ => value.push(b as char),
+                _ => value.push(b),
             }
         }
         if !terminated {
-            let lo = self.cur_pos() - value.len() - 1;
-            let hi = lo + value.find('\n').unwrap_or(0) + 1;
-            self.sess.new_error().fatal("unterminated documentation block").span(lo, hi).emit();
-            unreachable!()
+            let hi = self.line_end();
+            self.report("unterminated documentation block")
+                .code(codes::UNTERMINATED_DOC)
+                .span(start_pos, hi)
+                .emit();
+            return self.fail();
         }
-        TokenKind::Doc(value)
+        TokenKind::Doc(self.decode(value, value_lo))
     }
 
-    fn block_comment(&mut self) -> TokenKind {
-        let mut value = String::new();
+    fn block_comment(&mut self, start_pos: usize) -> TokenKind {
+        let mut value = vec![];
         let mut terminated = false;
         self.next_byte(); // skip the first /
+        let value_lo = self.cur_pos();
+        // only '/;' terminates, any other '/' is part of the comment
         while let Some(b) = self.next_byte() {
-            match b {
-                b'/' => {
-                    if self.peek_byte() == b';' {
-                        terminated = true;
-                        self.next_byte();
-                        break;
-                    }
-                    value.push(b as char)
-                }
-                _ => value.push(b as char),
+            if b == b'/' && self.peek_byte() == b';' {
+                self.next_byte();
+                terminated = true;
+                break;
             }
+            value.push(b);
         }
         if !terminated {
-            let lo = self.cur_pos() - value.len() - 2;
-            let hi = lo + value.find('\n').unwrap_or(0) + 2;
-            self.sess.new_error().fatal("unterminated block comment").span(lo, hi).emit();
-            unreachable!()
+            // usually a line comment starting with a slash rather than a real block comment
+            self.report("unterminated block comment")
+                .code(codes::UNTERMINATED_BLOCK_COMMENT)
+                .span(start_pos, start_pos + 2)
+                .label_error("comment is never closed with '/;'")
+                .footer_help("did you mean a line comment (';')?")
+                .emit();
+            return self.fail();
         }
-        TokenKind::Comment(value)
+        TokenKind::Comment(self.decode(value, value_lo))
     }
 
     fn line_comment(&mut self) -> TokenKind {
-        let mut value = String::new();
+        let value_lo = self.cur_pos();
+        let mut value = vec![];
         while !is_newline_start(self.peek_byte()) && !self.is_eof() {
-            value.push(self.next_byte().unwrap() as char);
+            value.push(self.next_byte().unwrap());
         }
-        TokenKind::Comment(value)
+        TokenKind::Comment(self.decode(value, value_lo))
     }
 
-    fn string(&mut self) -> LitKind {
-        let mut value = String::new();
+    // scanners collect raw bytes so multi-byte characters are kept intact
+    fn decode(&self, value: Vec<u8>, lo: usize) -> String {
+        String::from_utf8(value).unwrap_or_else(|err| {
+            self.sess
+                .new_error()
+                .error("invalid utf-8")
+                .code(codes::INVALID_UTF8)
+                .span(lo, self.cur_pos())
+                .label_error("invalid characters were replaced")
+                .emit();
+            String::from_utf8_lossy(err.as_bytes()).into_owned()
+        })
+    }
+
+    fn string(&mut self, start_pos: usize) -> TokenKind {
+        let value_lo = self.cur_pos();
+        let mut value = vec![];
         let mut terminated = false;
-        while let Some(b) = self.next_byte() {
-            match b {
+        // invalid escapes are all reported before failing, so the string is still scanned
+        let mut invalid = false;
+        while !self.is_eof() && !is_newline_start(self.peek_byte()) {
+            match self.next_byte().unwrap() {
                 b'"' => {
                     terminated = true;
                     break;
                 }
-                b if is_newline_start(b) => break,
                 b'\\' => {
                     match self.peek_byte() {
-                        b'n' => value.push('\n'),
-                        b't' => value.push('\t'),
-                        b'\\' => value.push('\\'),
-                        b'"' => value.push('"'),
+                        b'n' => value.push(b'\n'),
+                        b'r' => value.push(b'\r'),
+                        b't' => value.push(b'\t'),
+                        b'\\' => value.push(b'\\'),
+                        b'"' => value.push(b'"'),
+                        b'u' => {
+                            let lo = self.cur_pos() - 1;
+                            self.next_byte(); // skip the u
+                            let mut digits = String::new();
+                            while digits.len() < 4 && self.peek_byte().is_ascii_hexdigit() {
+                                digits.push(self.next_byte().unwrap() as char);
+                            }
+                            let c = u32::from_str_radix(&digits, 16).ok().and_then(char::from_u32);
+                            match c {
+                                Some(c) if digits.len() == 4 => {
+                                    value.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes())
+                                }
+                                _ => {
+                                    self.report("invalid unicode escape")
+                                        .code(codes::INVALID_UNICODE_ESCAPE)
+                                        .span(lo, self.cur_pos())
+                                        .label_error("expected four hex digits, e.g. '\\u00e9'")
+                                        .emit();
+                                    invalid = true;
+                                }
+                            }
+                            continue;
+                        }
                         _ => {
-                            self.sess
-                                .new_error()
-                                .fatal("invalid escape character")
+                            self.report("invalid escape character")
+                                .code(codes::INVALID_ESCAPE)
                                 .span(self.cur_pos() - 1, self.cur_pos() + 1)
-                                .label_error("only '\\n','\\t', '\\\\' or '\\\"' allowed")
+                                .label_error(
+                                    "only '\\n', '\\r', '\\t', '\\\\', '\\\"' or '\\uXXXX' allowed",
+                                )
                                 .emit();
-                            unreachable!()
+                            invalid = true;
+                            if is_newline_start(self.peek_byte()) {
+                                continue;
+                            }
                         }
                     }
                     self.next_byte();
                 }
-                _ => value.push(b as char),
+                b => value.push(b),
             }
         }
         if !terminated {
-            let lo = self.cur_pos() - value.len() - 2;
-            let hi = lo + value.len() + 1;
-            self.sess.new_error().fatal("unterminated string").span(lo, hi).emit();
-            unreachable!()
+            let hi = self.line_end();
+            self.report("unterminated string")
+                .code(codes::UNTERMINATED_STRING)
+                .span(start_pos, hi)
+                .emit();
+            return self.fail();
+        }
+        if invalid {
+            return self.fail();
         }
-        LitKind::Str(value)
+        TokenKind::Literal(LitKind::Str(self.decode(value, value_lo)))
     }
 
-    fn number(&mut self, first_digit: u8) -> LitKind {
+    fn number(&mut self, first_digit: u8) -> TokenKind {
         let mut value = String::new();
         if first_digit == b'0' && self.peek_byte() == b'x' {
             // hex literal
@@ -386,17 +967,18 @@ impl<'a> Lexer<'a> {
                 };
                 self.next_byte();
             }
-            if let Ok(lit) = i32::from_str_radix(&value, 16) {
-                return LitKind::Integer(lit, true);
+            // hex literals are 32-bit patterns, so form ids like 0xFF000800 wrap to negative
+            if let Ok(lit) = u32::from_str_radix(&value, 16) {
+                return TokenKind::Literal(LitKind::Integer(lit as i32, true));
             } else {
                 let hi = self.cur_pos();
                 let lo = hi - value.len() - 2;
-                self.sess
-                    .new_error()
-                    .fatal("could not parse hex literal")
+                self.report("could not parse hex literal")
+                    .code(codes::INVALID_HEX)
                     .span(lo, hi)
                     .label_error("not a valid hex literal")
                     .emit();
+                return self.fail();
             }
         }
         value.push(first_digit as char);
@@ -404,6 +986,19 @@ impl<'a> Lexer<'a> {
         while !self.is_eof() {
             match self.peek_byte() {
                 b @ b'0'..=b'9' => value.push(b as char),
+                b'.' if is_float => {
+                    let lo = self.cur_pos();
+                    self.report("floats may only contain one decimal point")
+                        .code(codes::MULTIPLE_DECIMAL_POINTS)
+                        .span(lo, lo + 1)
+                        .label_error("second decimal point")
+                        .emit();
+                    while matches!(self.peek_byte(), b'0'..=b'9' | b'.') {
+                        self.next_byte();
+                    }
+                    return self.fail();
+                }
+                // a trailing dot is accepted, '5.' is the same as '5.0'
                 b'.' => {
                     is_float = true;
                     value.push('.');
@@ -412,46 +1007,238 @@ impl<'a> Lexer<'a> {
             }
             self.next_byte();
         }
+        if let b'e' | b'E' = self.peek_byte() {
+            // exponent, e.g. 1.5e3 or 2E-4
+            is_float = true;
+            value.push(self.next_byte().unwrap() as char);
+            if let b'+' | b'-' = self.peek_byte() {
+                value.push(self.next_byte().unwrap() as char);
+            }
+            let digits_start = value.len();
+            while self.peek_byte().is_ascii_digit() {
+                value.push(self.next_byte().unwrap() as char);
+            }
+            if value.len() == digits_start {
+                let hi = self.cur_pos();
+                let lo = hi - value.len();
+                self.report("missing exponent digits in float literal")
+                    .code(codes::MISSING_EXPONENT)
+                    .span(lo, hi)
+                    .label_help("add digits after the exponent, e.g. '1.5e3'")
+                    .emit();
+                return self.fail();
+            }
+        }
         if is_float {
             if let Ok(lit) = value.parse::<f32>() {
-                LitKind::Float(lit)
+                TokenKind::Literal(LitKind::Float(lit, value))
             } else {
                 let hi = self.cur_pos();
                 let lo = hi - value.len();
-                self.sess.new_error().fatal("could not parse float literal").span(lo, hi).emit();
-                unreachable!()
+                self.report("could not parse float literal")
+                    .code(codes::INVALID_FLOAT)
+                    .span(lo, hi)
+                    .emit();
+                self.fail()
             }
         } else if let Ok(lit) = value.parse::<i32>() {
-            LitKind::Integer(lit, false)
+            TokenKind::Literal(LitKind::Integer(lit, false))
+        } else if value.parse::<i64>() == Ok(1 << 31) {
+            // only valid once negated, which the parser checks
+            TokenKind::Literal(LitKind::Integer(i32::MIN, false))
         } else {
             let hi = self.cur_pos();
             let lo = hi - value.len();
-            self.sess
-                .new_error()
-                .fatal("could not parse integer literal")
+            self.report("could not parse integer literal")
+                .code(codes::INVALID_INTEGER)
                 .span(lo, hi)
                 .label_help("try using a smaller integer")
                 .emit();
-            unreachable!()
+            self.fail()
         }
     }
 
     fn ident(&mut self, first_char: u8) -> TokenKind {
+        let lo = self.cur_pos() - 1;
         let mut value = String::new();
         value.push(first_char as char);
-        while is_id_continue(self.peek_byte(), &self.sess.game) {
+        loop {
+            match self.peek_byte() {
+                // a separator is only part of the identifier if another part follows it
+                b':' if self.sess.game == Game::FO4 => {
+                    if !is_id_start(self.peek_second_byte()) {
+                        let pos = self.cur_pos();
+                        self.sess
+                            .new_error()
+                            .error("invalid namespace separator")
+                            .code(codes::INVALID_NAMESPACE)
+                            .span(pos, pos + 1)
+                            .label_error("expected an identifier after ':'")
+                            .emit();
+                        break;
+                    }
+                }
+                b if is_id_continue(b) => (),
+                _ => break,
+            }
             value.push(self.next_byte().unwrap() as char);
         }
-        if let Ok(kind) = KwKind::from_str(&value.to_ascii_lowercase()) {
-            TokenKind::Keyword(kind)
+        match self.sess.max_ident_len {
+            Some(max) if value.len() > max => self
+                .sess
+                .new_error()
+                .warning(&format!("identifier is longer than {} bytes", max))
+                .code(codes::IDENT_TOO_LONG)
+                .span(lo, self.cur_pos())
+                .label_warning("the game compiler may reject this identifier")
+                .emit(),
+            _ => (),
+        }
+        if let Some(kind) = KwKind::from_ident(&value, self.sess.game) {
+            match kind {
+                KwKind::True => TokenKind::Literal(LitKind::Bool(true)),
+                KwKind::False => TokenKind::Literal(LitKind::Bool(false)),
+                KwKind::None => TokenKind::Literal(LitKind::None),
+                kind => TokenKind::Keyword(kind),
+            }
         } else {
             TokenKind::Ident(value)
         }
     }
 }
 
+/// Yields every token in the source, including the final `Eof` token.
+///
+/// Once `Eof` has been yielded the lexer is exhausted and `next()` always returns `None`.
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        if self.finished {
+            return None;
+        }
+        let token = self.next_token();
+        if token.kind == TokenKind::Eof {
+            self.finished = true;
+        }
+        Some(token)
+    }
+}
+
+/// A change to a source: the bytes `lo..hi` of the old source were replaced by `len` bytes.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Edit {
+    pub lo: usize,
+    pub hi: usize,
+    pub len: usize,
+}
+
+// lexes the edited source of `lexer` again, reusing every token of `old` not affected by `edit`
+pub(crate) fn relex(mut lexer: Lexer<'_>, old: &[Token], edit: Edit) -> Vec<Token> {
+    // the first token touching the edit, plus the one before in case they now merge
+    let first = old.iter().position(|token| token.hi >= edit.lo).unwrap_or(old.len());
+    let start = first.saturating_sub(1);
+    let spans_lines = |token: &Token| match &token.kind {
+        TokenKind::Comment(text) | TokenKind::Doc(text) => text.contains(['\r', '\n']),
+        _ => false,
+    };
+    if old[first..].iter().take_while(|token| token.lo <= edit.hi).any(spans_lines) {
+        return lexer.collect();
+    }
+    let restart = old.get(start).map_or(0, Token::lo);
+    lexer.restore(Checkpoint {
+        pos: restart,
+        finished: false,
+        newline_skipped: false,
+        doc_skipped: None,
+    });
+    let mut tokens = old[..start].to_vec();
+    let edit_end = edit.lo + edit.len;
+    // offsets after the edit moved by this much
+    let shift = |pos: usize| pos + edit.len - (edit.hi - edit.lo);
+    let mut rest = old[start..].iter().skip_while(|token| token.lo < edit.hi).peekable();
+    while let Some(token) = lexer.next() {
+        tokens.push(token);
+        // back in sync once both streams reach the same token boundary after the edit
+        let pos = lexer.cur_pos();
+        if pos < edit_end || lexer.finished {
+            continue;
+        }
+        while rest.peek().is_some_and(|old| shift(old.lo) < pos) {
+            rest.next();
+        }
+        if rest.peek().is_some_and(|old| shift(old.lo) == pos) {
+            let shifted =
+                rest.map(|old| Token::new(old.kind.clone(), shift(old.lo), shift(old.hi)));
+            tokens.extend(shifted);
+            break;
+        }
+    }
+    tokens
+}
+
+// a significant token along with the trivia skipped before it
+struct Significant {
+    token: Token,
+    newline_before: bool,
+    doc_before: Option<Token>,
+}
+
+/// Significant tokens of a `Lexer` with arbitrary lookahead.
+///
+/// Tokens are lexed on demand and buffered until bumped, so nothing is lexed twice. Once the
+/// source is exhausted every lookahead past the end is `Eof`.
+pub struct TokenStream<'a> {
+    lexer: Lexer<'a>,
+    buffer: VecDeque<Significant>,
+}
+
+impl<'a> TokenStream<'a> {
+    pub fn new(lexer: Lexer<'a>) -> Self {
+        Self { lexer, buffer: VecDeque::new() }
+    }
+
+    fn fill(&mut self, n: usize) -> &Significant {
+        while self.buffer.len() <= n {
+            let token = self.lexer.next_significant_token();
+            let newline_before = self.lexer.newline_skipped();
+            let doc_before = self.lexer.doc_skipped.take();
+            self.buffer.push_back(Significant { token, newline_before, doc_before });
+        }
+        &self.buffer[n]
+    }
+
+    /// The next token, without consuming it.
+    pub fn peek(&mut self) -> &Token {
+        self.peek_nth(0)
+    }
+
+    /// The token `n` positions ahead, `peek_nth(0)` being the next one.
+    pub fn peek_nth(&mut self, n: usize) -> &Token {
+        &self.fill(n).token
+    }
+
+    /// Whether a newline was skipped right before the token `n` positions ahead.
+    pub fn newline_before(&mut self, n: usize) -> bool {
+        self.fill(n).newline_before
+    }
+
+    /// The last documentation block skipped right before the token `n` positions ahead.
+    pub fn doc_before(&mut self, n: usize) -> Option<&Token> {
+        self.fill(n).doc_before.as_ref()
+    }
+
+    /// Consumes and returns the next token.
+    pub fn bump(&mut self) -> Token {
+        self.fill(0);
+        self.buffer.pop_front().unwrap().token
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use std::io::{Cursor, Read};
+
     use super::*;
 
     #[test]
@@ -468,7 +1255,46 @@ mod tests {
         let mut lexer = Lexer::from_sess(&sess);
         assert_eq!(Token::new(TokenKind::Newline(false), 0, 1), lexer.next_token());
         lexer.next_token();
-        assert_eq!(Token::new(TokenKind::Newline(true), 2, 4), lexer.next_token());
+        let token = lexer.next_token();
+        assert_eq!(Token::new(TokenKind::Newline(true), 2, 4), token);
+        assert_eq!((token.lo(), token.hi()), token.span());
+    }
+
+    #[test]
+    fn line_continuation() {
+        let sess = ParserSession::from_string("Foo(a, \\\r\n b)", Game::TESV);
+        let tokens = sess.tokenize();
+        assert_eq!(Token::new(TokenKind::LineContinuation, 7, 10), tokens[5]);
+        let mut lexer = Lexer::from_sess(&sess);
+        let kinds = (0..5).map(|_| lexer.next_significant_token().kind).collect::<Vec<_>>();
+        assert_eq!(TokenKind::Ident("b".to_string()), kinds[4]);
+        assert!(!lexer.newline_skipped());
+    }
+
+    #[test]
+    fn max_ident_len() {
+        let sess = ParserSession::from_string("Int abcdef = abc", Game::TESV).with_max_ident_len(5);
+        sess.tokenize();
+        let diagnostics = sess.diagnostics();
+        assert_eq!(1, diagnostics.len());
+        assert_eq!("identifier is longer than 5 bytes", diagnostics[0].title);
+        assert_eq!(Some(codes::IDENT_TOO_LONG.to_string()), diagnostics[0].code);
+        assert_eq!((4, 10), diagnostics[0].span);
+
+        let script = format!("Int {}", "a".repeat(65536));
+        let sess = ParserSession::from_string(&script, Game::TESV);
+        sess.tokenize();
+        assert_eq!("identifier is longer than 65535 bytes", sess.diagnostics()[0].title);
+    }
+
+    #[test]
+    fn max_line_len() {
+        let script = "x = 1\r\nFoo(a, \\\n b)\nx = 12\nx";
+        let sess = ParserSession::from_string(script, Game::TESV).with_max_line_len(5);
+        sess.tokenize();
+        let spans = sess.diagnostics().iter().map(|d| d.span).collect::<Vec<_>>();
+        assert_eq!(vec![(7, 19), (20, 26)], spans);
+        assert_eq!("line is longer than 5 bytes", sess.diagnostics()[0].title);
     }
 
     #[test]
@@ -507,4 +1333,565 @@ mod tests {
             lexer.next_token()
         );
     }
+
+    #[test]
+    fn significant() {
+        let sess = ParserSession::from_string("a ; one\n\t;/ two /; {doc} b  c\n", Game::TESV);
+        let mut lexer = Lexer::from_sess(&sess);
+        assert_eq!(
+            Token::new(TokenKind::Ident("a".to_string()), 0, 1),
+            lexer.next_significant_token()
+        );
+        assert!(!lexer.newline_skipped());
+        assert_eq!(TokenKind::Ident("b".to_string()), lexer.next_significant_token().kind);
+        assert!(lexer.newline_skipped());
+        assert_eq!(Some((19, 24)), lexer.doc_skipped().map(Token::span));
+        assert_eq!(TokenKind::Ident("c".to_string()), lexer.next_significant_token().kind);
+        assert!(!lexer.newline_skipped());
+        assert_eq!(None, lexer.doc_skipped());
+        assert_eq!(TokenKind::Eof, lexer.next_significant_token().kind);
+        assert!(lexer.newline_skipped());
+    }
+
+    #[test]
+    fn token_stream() {
+        let sess = ParserSession::from_string("Int[] x\n{doc} y", Game::TESV);
+        let mut tokens = TokenStream::new(Lexer::from_sess(&sess));
+        assert_eq!(TokenKind::Keyword(KwKind::Int), tokens.peek().kind);
+        assert_eq!(TokenKind::Ident("x".to_string()), tokens.peek_nth(3).kind);
+        assert_eq!(TokenKind::Ident("y".to_string()), tokens.peek_nth(4).kind);
+        assert!(tokens.newline_before(4));
+        assert_eq!(Some((8, 13)), tokens.doc_before(4).map(Token::span));
+        assert_eq!(TokenKind::Keyword(KwKind::Int), tokens.bump().kind);
+        assert_eq!(TokenKind::LSquare, tokens.bump().kind);
+        assert_eq!(TokenKind::RSquare, tokens.peek().kind);
+        assert!(!tokens.newline_before(0));
+    }
+
+    #[test]
+    fn token_stream_eof() {
+        let sess = ParserSession::from_string("x", Game::TESV);
+        let mut tokens = TokenStream::new(Lexer::from_sess(&sess));
+        assert_eq!(TokenKind::Eof, tokens.peek_nth(3).kind);
+        assert_eq!(TokenKind::Ident("x".to_string()), tokens.bump().kind);
+        assert_eq!(TokenKind::Eof, tokens.bump().kind);
+        assert_eq!(TokenKind::Eof, tokens.bump().kind);
+        assert_eq!(TokenKind::Eof, tokens.peek().kind);
+    }
+
+    #[test]
+    fn fatal_stops_lexing() {
+        let sess = ParserSession::from_string("x \"abc\\q\" y", Game::TESV);
+        let tokens = sess.tokenize();
+        assert_eq!(Token::new(TokenKind::Eof, 2, 11), tokens[2]);
+        assert_eq!(3, tokens.len());
+        assert!(sess.has_fatal());
+        assert_eq!(1, sess.diagnostics().len());
+    }
+
+    #[test]
+    fn iterator() {
+        let script = "ScriptName Foo\nInt x = 5 ; comment";
+        let sess = ParserSession::from_string(script, Game::TESV);
+        let mut lexer = Lexer::from_sess(&sess);
+        let mut expected = vec![];
+        loop {
+            let token = lexer.next_token();
+            let is_eof = token.kind == TokenKind::Eof;
+            expected.push(token);
+            if is_eof {
+                break;
+            }
+        }
+        let mut lexer = Lexer::from_sess(&sess);
+        assert_eq!(expected, lexer.by_ref().collect::<Vec<_>>());
+        assert_eq!(None, lexer.next());
+        assert_eq!(None, lexer.next());
+    }
+
+    #[test]
+    fn tokenize_str() {
+        let kinds = super::tokenize_str("x = \"", Game::TESV)
+            .into_iter()
+            .map(|token| token.kind)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                TokenKind::Ident("x".to_string()),
+                TokenKind::Whitespace,
+                TokenKind::Equal,
+                TokenKind::Whitespace,
+                TokenKind::Eof,
+            ],
+            kinds
+        );
+    }
+
+    #[test]
+    fn tokenize_empty() {
+        let sess = ParserSession::from_string("", Game::TESV);
+        assert_eq!(vec![Token::new(TokenKind::Eof, 0, 0)], sess.tokenize());
+    }
+
+    #[test]
+    fn minus() {
+        let kinds = |script| {
+            let sess = ParserSession::from_string(script, Game::TESV);
+            sess.tokenize().into_iter().map(|token| token.kind).collect::<Vec<_>>()
+        };
+        let int = |value| TokenKind::Literal(LitKind::Integer(value, false));
+        assert_eq!(vec![int(3), TokenKind::Minus, int(5), TokenKind::Eof], kinds("3-5"));
+        assert_eq!(vec![TokenKind::Minus, int(5), TokenKind::Eof], kinds("-5"));
+        assert_eq!(vec![TokenKind::MinusEq, int(5), TokenKind::Eof], kinds("-=5"));
+    }
+
+    #[test]
+    fn float_exponent() {
+        let sess = ParserSession::from_string("1.5e3 2E-4 3e+2", Game::TESV);
+        let kinds = sess.tokenize().into_iter().map(|token| token.kind).collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                TokenKind::Literal(LitKind::Float(1.5e3, "1.5e3".to_string())),
+                TokenKind::Whitespace,
+                TokenKind::Literal(LitKind::Float(2E-4, "2E-4".to_string())),
+                TokenKind::Whitespace,
+                TokenKind::Literal(LitKind::Float(3e+2, "3e+2".to_string())),
+                TokenKind::Eof,
+            ],
+            kinds
+        );
+    }
+
+    #[test]
+    fn float_exponent_missing_digits() {
+        let sess = ParserSession::from_string("1.5e", Game::TESV);
+        let mut lexer = Lexer::from_sess(&sess);
+        assert_eq!(TokenKind::Eof, lexer.next_token().kind);
+        assert_eq!("missing exponent digits in float literal", sess.diagnostics()[0].title);
+        assert_eq!((0, 4), sess.diagnostics()[0].span);
+    }
+
+    #[test]
+    fn float_decimal_point() {
+        let sess = ParserSession::from_string("1.2 5.", Game::TESV);
+        let mut lexer = Lexer::from_sess(&sess);
+        assert_eq!(
+            Token::new(TokenKind::Literal(LitKind::Float(1.2, "1.2".to_string())), 0, 3),
+            lexer.next_token()
+        );
+        lexer.next_token();
+        assert_eq!(
+            Token::new(TokenKind::Literal(LitKind::Float(5.0, "5.".to_string())), 4, 6),
+            lexer.next_token()
+        );
+        assert!(sess.diagnostics().is_empty());
+    }
+
+    #[test]
+    fn float_two_decimal_points() {
+        let sess = ParserSession::from_string("1.2.3", Game::TESV);
+        assert_eq!(TokenKind::Eof, Lexer::from_sess(&sess).next_token().kind);
+        assert_eq!("floats may only contain one decimal point", sess.diagnostics()[0].title);
+        assert_eq!((3, 4), sess.diagnostics()[0].span);
+    }
+
+    #[test]
+    fn bool_literal() {
+        let sess = ParserSession::from_string("True false", Game::TESV);
+        let mut lexer = Lexer::from_sess(&sess);
+        assert_eq!(Token::new(TokenKind::Literal(LitKind::Bool(true)), 0, 4), lexer.next_token());
+        lexer.next_token();
+        assert_eq!(TokenKind::Literal(LitKind::Bool(false)), lexer.next_token().kind);
+    }
+
+    #[test]
+    fn hex_integer() {
+        let sess = ParserSession::from_string("0xFFFFFFFF 0x7FFFFFFF", Game::TESV);
+        let mut lexer = Lexer::from_sess(&sess);
+        assert_eq!(TokenKind::Literal(LitKind::Integer(-1, true)), lexer.next_token().kind);
+        lexer.next_token();
+        assert_eq!(TokenKind::Literal(LitKind::Integer(i32::MAX, true)), lexer.next_token().kind);
+        assert!(sess.diagnostics().is_empty());
+    }
+
+    #[test]
+    fn integer_overflow() {
+        let sess = ParserSession::from_string("2147483648", Game::TESV);
+        let kind = Lexer::from_sess(&sess).next_token().kind;
+        assert_eq!(TokenKind::Literal(LitKind::Integer(i32::MIN, false)), kind);
+        assert!(sess.diagnostics().is_empty());
+        let sess = ParserSession::from_string("2147483649", Game::TESV);
+        assert_eq!(TokenKind::Eof, Lexer::from_sess(&sess).next_token().kind);
+        assert_eq!("could not parse integer literal", sess.diagnostics()[0].title);
+        assert_eq!((0, 10), sess.diagnostics()[0].span);
+    }
+
+    #[test]
+    fn string_escapes() {
+        let sess = ParserSession::from_string(r#""a\r\n\u00e9\u00E9""#, Game::TESV);
+        assert_eq!(
+            TokenKind::Literal(LitKind::Str("a\r\néé".to_string())),
+            Lexer::from_sess(&sess).next_token().kind
+        );
+        assert!(sess.diagnostics().is_empty());
+    }
+
+    #[test]
+    fn string_invalid_unicode_escape() {
+        let sess = ParserSession::from_string(r#""a\u12" x"#, Game::TESV);
+        assert_eq!(TokenKind::Eof, Lexer::from_sess(&sess).next_token().kind);
+        assert_eq!("invalid unicode escape", sess.diagnostics()[0].title);
+        assert_eq!((2, 6), sess.diagnostics()[0].span);
+    }
+
+    #[test]
+    fn string_invalid_escapes() {
+        let sess = ParserSession::from_string(r#"x = "a\qb\u12z\!" + 1"#, Game::TESV);
+        let mut lexer = Lexer::from_sess(&sess);
+        lexer.set_recover(true);
+        let kinds = lexer.map(|token| token.kind).collect::<Vec<_>>();
+        assert_eq!(TokenKind::Unknown, kinds[4]);
+        assert_eq!(TokenKind::Plus, kinds[6]);
+        let diagnostics = sess.diagnostics();
+        assert_eq!(3, diagnostics.len());
+        assert_eq!("invalid escape character", diagnostics[0].title);
+        assert_eq!((6, 8), diagnostics[0].span);
+        assert_eq!("invalid unicode escape", diagnostics[1].title);
+        assert_eq!((9, 13), diagnostics[1].span);
+        assert_eq!("invalid escape character", diagnostics[2].title);
+        assert_eq!((14, 16), diagnostics[2].span);
+    }
+
+    #[test]
+    fn unicode() {
+        let sess = ParserSession::from_string("{ café }\"ça\";/é/;; é", Game::TESV);
+        let kinds = sess.tokenize().into_iter().map(|token| token.kind).collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                TokenKind::Doc(" café ".to_string()),
+                TokenKind::Literal(LitKind::Str("ça".to_string())),
+                TokenKind::Comment("é".to_string()),
+                TokenKind::Comment(" é".to_string()),
+                TokenKind::Eof,
+            ],
+            kinds
+        );
+    }
+
+    #[test]
+    fn unterminated_spans() {
+        let sess = ParserSession::from_string("x \"a\\n\\tb", Game::TESV);
+        sess.tokenize();
+        assert_eq!("unterminated string", sess.diagnostics()[0].title);
+        assert_eq!((2, 9), sess.diagnostics()[0].span);
+
+        let sess = ParserSession::from_string("x \"a\\nb\ny\"", Game::TESV);
+        sess.tokenize();
+        assert_eq!((2, 7), sess.diagnostics()[0].span);
+
+        let sess = ParserSession::from_string("x ;/ a\nb", Game::TESV);
+        sess.tokenize();
+        assert_eq!("unterminated block comment", sess.diagnostics()[0].title);
+        assert_eq!((2, 4), sess.diagnostics()[0].span);
+
+        let sess = ParserSession::from_string("x { a\r\nb", Game::TESV);
+        sess.tokenize();
+        assert_eq!("unterminated documentation block", sess.diagnostics()[0].title);
+        assert_eq!((2, 5), sess.diagnostics()[0].span);
+    }
+
+    #[test]
+    fn block_comment_slashes() {
+        let sess = ParserSession::from_string(";/a/b/;;/ // /;;/ x //;;//;", Game::TESV);
+        let kinds = sess.tokenize().into_iter().map(|token| token.kind).collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                TokenKind::Comment("a/b".to_string()),
+                TokenKind::Comment(" // ".to_string()),
+                TokenKind::Comment(" x /".to_string()),
+                TokenKind::Comment("".to_string()),
+                TokenKind::Eof,
+            ],
+            kinds
+        );
+    }
+
+    #[test]
+    fn block_comment_to_eof() {
+        let sess = ParserSession::from_string("Int x ;/ the count\nInt y", Game::TESV);
+        sess.tokenize();
+        let diagnostic = &sess.diagnostics()[0];
+        assert_eq!((6, 8), diagnostic.span);
+        assert_eq!("did you mean a line comment (';')?", diagnostic.footer[0].1);
+    }
+
+    #[test]
+    fn unknown() {
+        let sess = ParserSession::from_string("a§b $", Game::TESV);
+        let tokens = sess.tokenize();
+        assert_eq!(
+            vec![
+                Token::new(TokenKind::Ident("a".to_string()), 0, 1),
+                Token::new(TokenKind::Unknown, 1, 3),
+                Token::new(TokenKind::Ident("b".to_string()), 3, 4),
+                Token::new(TokenKind::Whitespace, 4, 5),
+                Token::new(TokenKind::Unknown, 5, 6),
+                Token::new(TokenKind::Eof, 6, 6),
+            ],
+            tokens
+        );
+        let diagnostics = sess.diagnostics();
+        assert_eq!(2, diagnostics.len());
+        assert_eq!((1, 3), diagnostics[0].span);
+        assert_eq!((5, 6), diagnostics[1].span);
+        assert!(!sess.has_fatal());
+    }
+
+    #[test]
+    fn recover() {
+        let script = "x = 1.2.3\ny = \"a\\qb\" + 1e\nz = 9999999999 ;/ open";
+        let sess = ParserSession::from_string(script, Game::TESV);
+        let mut lexer = Lexer::from_sess(&sess);
+        lexer.set_recover(true);
+        let kinds = lexer.map(|token| token.kind).collect::<Vec<_>>();
+        assert_eq!(24, kinds.len());
+        assert_eq!(5, kinds.iter().filter(|kind| **kind == TokenKind::Unknown).count());
+        assert_eq!(5, sess.diagnostics().len());
+        assert!(!sess.has_fatal());
+    }
+
+    #[test]
+    fn discriminant_eq() {
+        let int = |value| TokenKind::Literal(LitKind::Integer(value, false));
+        assert!(int(1).discriminant_eq(&int(99)));
+        assert!(int(1).discriminant_eq(&TokenKind::Literal(LitKind::Integer(1, true))));
+        assert!(!int(1).discriminant_eq(&TokenKind::Literal(LitKind::Str("1".to_string()))));
+        let ident = |name: &str| TokenKind::Ident(name.to_string());
+        assert!(ident("a").discriminant_eq(&ident("b")));
+        assert!(!ident("a").discriminant_eq(&int(1)));
+        assert!(!TokenKind::Keyword(KwKind::If).discriminant_eq(&TokenKind::Keyword(KwKind::While)));
+    }
+
+    #[test]
+    fn keyword_classes() {
+        assert!(KwKind::Int.is_type());
+        assert!(KwKind::Var.is_type());
+        assert!(!KwKind::If.is_type());
+        assert!(KwKind::Native.is_modifier());
+        assert!(KwKind::Const.is_modifier());
+        assert!(!KwKind::Function.is_modifier());
+        assert!(KwKind::Struct.is_fo4_only());
+        assert!(!KwKind::While.is_fo4_only());
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(",", TokenKind::Comma.to_string());
+        assert_eq!("==", TokenKind::CmpEQ.to_string());
+        assert_eq!("%=", TokenKind::ModuleEq.to_string());
+        assert_eq!("If", TokenKind::Keyword(KwKind::If).to_string());
+        assert_eq!("Self", TokenKind::Keyword(KwKind::_Self).to_string());
+        assert_eq!("EndFunction", KwKind::EndFunction.to_string());
+        assert_eq!("string literal", TokenKind::Literal(LitKind::Str("a".to_string())).to_string());
+        assert_eq!("identifier", TokenKind::Ident("a".to_string()).to_string());
+        assert_eq!("end of file", TokenKind::Eof.to_string());
+    }
+
+    #[test]
+    fn fo4_keywords() {
+        let sess = ParserSession::from_string("Var", Game::TESV);
+        assert_eq!(TokenKind::Ident("Var".to_string()), Lexer::from_sess(&sess).next_token().kind);
+        let sess = ParserSession::from_string("Var", Game::FO4);
+        assert_eq!(TokenKind::Keyword(KwKind::Var), Lexer::from_sess(&sess).next_token().kind);
+        assert_eq!(Some(KwKind::While), KwKind::from_ident("WHILE", Game::TESV));
+        assert_eq!(None, KwKind::from_ident("struct", Game::TESV));
+    }
+
+    #[test]
+    fn namespace() {
+        let sess = ParserSession::from_string("Foo:Bar:Baz", Game::FO4);
+        let token = Lexer::from_sess(&sess).next_token();
+        assert_eq!(TokenKind::Ident("Foo:Bar:Baz".to_string()), token.kind);
+        assert_eq!(vec!["Foo", "Bar", "Baz"], split_namespace("Foo:Bar:Baz"));
+        assert!(sess.diagnostics().is_empty());
+    }
+
+    #[test]
+    fn namespace_malformed() {
+        let sess = ParserSession::from_string("Foo: Foo::Bar", Game::FO4);
+        let kinds = Lexer::from_sess(&sess).map(|token| token.kind).collect::<Vec<_>>();
+        assert_eq!(TokenKind::Ident("Foo".to_string()), kinds[0]);
+        assert_eq!(TokenKind::Colon, kinds[1]);
+        assert_eq!(
+            vec![
+                TokenKind::Ident("Foo".to_string()),
+                TokenKind::Colon,
+                TokenKind::Colon,
+                TokenKind::Ident("Bar".to_string()),
+            ],
+            kinds[3..7]
+        );
+        let diagnostics = sess.diagnostics();
+        assert_eq!(2, diagnostics.len());
+        assert_eq!((3, 4), diagnostics[0].span);
+        assert_eq!((8, 9), diagnostics[1].span);
+        assert!(!sess.has_fatal());
+    }
+
+    #[test]
+    fn colon() {
+        let sess = ParserSession::from_string("a : b", Game::FO4);
+        let tokens = sess.tokenize();
+        assert_eq!(Token::new(TokenKind::Colon, 2, 3), tokens[2]);
+        assert!(sess.diagnostics().is_empty());
+
+        let sess = ParserSession::from_string("Foo:Bar", Game::TESV);
+        let kinds = sess.tokenize().into_iter().map(|token| token.kind).collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                TokenKind::Ident("Foo".to_string()),
+                TokenKind::Colon,
+                TokenKind::Ident("Bar".to_string()),
+                TokenKind::Eof,
+            ],
+            kinds
+        );
+    }
+
+    #[test]
+    fn from_reader() {
+        let source = "ScriptName Foo\r\n{docs}\nInt Function Bar(\"é\", 1.5) ; x\n\"unterminated";
+        let sess = ParserSession::from_string(source, Game::TESV);
+        let expected = Lexer::from_sess(&sess).collect::<Vec<_>>();
+        let reader_sess = ParserSession::from_string("", Game::TESV);
+        let tokens =
+            Lexer::from_reader(&reader_sess, Cursor::new(source.as_bytes())).collect::<Vec<_>>();
+        assert_eq!(expected, tokens);
+        assert_eq!(sess.diagnostics()[0].span, reader_sess.diagnostics()[0].span);
+    }
+
+    #[test]
+    fn from_reader_error() {
+        struct Failing<'a>(&'a [u8]);
+
+        impl Read for Failing<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.0.is_empty() {
+                    return Err(io::Error::other("disconnected"));
+                }
+                self.0.read(buf)
+            }
+        }
+
+        let sess = ParserSession::from_string("", Game::TESV);
+        let reader = io::BufReader::new(Failing(b"Int x"));
+        let tokens = Lexer::from_reader(&sess, reader).map(|t| t.kind).collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                TokenKind::Keyword(KwKind::Int),
+                TokenKind::Whitespace,
+                TokenKind::Ident("x".to_string()),
+                TokenKind::Eof
+            ],
+            tokens
+        );
+        let diagnostics = sess.diagnostics();
+        assert_eq!(1, diagnostics.len());
+        assert_eq!("could not read script: disconnected", diagnostics[0].title);
+        assert!(diagnostics[0].fatal);
+        assert_eq!((5, 5), diagnostics[0].span);
+    }
+
+    #[test]
+    fn reset() {
+        let sess = ParserSession::from_string("Int x = 5 ; é\n{doc} Foo()", Game::TESV);
+        let mut lexer = Lexer::from_sess(&sess);
+        let tokens = lexer.by_ref().collect::<Vec<_>>();
+        lexer.reset();
+        assert_eq!(tokens, lexer.by_ref().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn checkpoint() {
+        let sess = ParserSession::from_string("Int x\nFoo()", Game::TESV);
+        let mut lexer = Lexer::from_sess(&sess);
+        lexer.next_significant_token();
+        let checkpoint = lexer.checkpoint();
+        let x = lexer.next_significant_token();
+        let foo = lexer.next_significant_token();
+        assert!(lexer.newline_skipped());
+        lexer.restore(checkpoint);
+        assert!(!lexer.newline_skipped());
+        assert_eq!(x, lexer.next_significant_token());
+        assert_eq!(foo, lexer.next_significant_token());
+    }
+
+    fn relex(old_source: &str, lo: usize, hi: usize, text: &str) -> (Vec<Token>, Vec<Token>) {
+        let sess = ParserSession::from_string(old_source, Game::TESV);
+        let old = sess.tokenize();
+        let new_source = format!("{}{}{}", &old_source[..lo], text, &old_source[hi..]);
+        let sess = ParserSession::from_string(&new_source, Game::TESV);
+        let edit = Edit { lo, hi, len: text.len() };
+        (sess.retokenize(&old, edit), sess.tokenize())
+    }
+
+    #[test]
+    fn retokenize() {
+        let source = "ScriptName Foo\nFunction Bar()\n\tcount += 1\nEndFunction\n";
+        let (relexed, expected) = relex(source, 30, 35, "total");
+        assert_eq!(expected, relexed);
+        let (relexed, expected) = relex(source, 30, 35, "x ;/ y");
+        assert_eq!(expected, relexed);
+        let (relexed, expected) = relex(source, 35, 35, "Count");
+        assert_eq!(expected, relexed);
+        let (relexed, expected) = relex(source, 0, source.len(), "");
+        assert_eq!(expected, relexed);
+    }
+
+    #[test]
+    fn retokenize_block_comment() {
+        let source = "Int x ;/ a\nb /; Int y";
+        let (relexed, expected) = relex(source, 11, 12, "/;");
+        assert_eq!(expected, relexed);
+    }
+
+    #[test]
+    fn trivia() {
+        let sess = ParserSession::from_string("Int x ; c\r\n{d} ;/b/;", Game::TESV);
+        let trivia = sess.tokenize().iter().map(Token::is_trivia).collect::<Vec<_>>();
+        assert_eq!(vec![false, true, false, true, true, true, true, true, true, false], trivia);
+    }
+
+    #[test]
+    fn keyword_text() {
+        let sess = ParserSession::from_string("ENDFUNCTION", Game::TESV);
+        let mut lexer = Lexer::from_sess(&sess);
+        let token = lexer.next_token();
+        assert_eq!(TokenKind::Keyword(KwKind::EndFunction), token.kind);
+        assert_eq!("ENDFUNCTION", lexer.text(&token));
+    }
+
+    #[test]
+    fn keyword_as_str() {
+        assert_eq!("AutoReadOnly", KwKind::AutoReadOnly.as_str());
+        assert_eq!("CustomEventName", KwKind::CustomEventName.as_str());
+        assert_eq!("ScriptName", KwKind::ScriptName.as_str());
+        assert_eq!("Self", KwKind::_Self.as_str());
+        let kind = KwKind::from_str(&KwKind::StructVarName.as_str().to_ascii_lowercase());
+        assert_eq!(Ok(KwKind::StructVarName), kind);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize() {
+        let sess = ParserSession::from_string("x = 1.5", Game::TESV);
+        let json = serde_json::to_value(sess.tokenize()).unwrap();
+        assert_eq!(serde_json::json!({"Ident": "x"}), json[0]["kind"]);
+        assert_eq!(serde_json::json!({"Literal": {"Float": 1.5}}), json[4]["kind"]);
+        assert_eq!(4, json[4]["lo"]);
+        assert_eq!(7, json[4]["hi"]);
+        assert_eq!(serde_json::json!("Eof"), json[5]["kind"]);
+        let kind: TokenKind = serde_json::from_value(json[4]["kind"].clone()).unwrap();
+        assert_eq!(TokenKind::Literal(LitKind::Float(1.5, "1.5".to_string())), kind);
+    }
 }
@@ -1,88 +1,537 @@
+use std::cell::{Ref, RefCell};
+use std::env;
 use std::ffi::OsString;
 use std::fs;
-use std::io::BufRead;
+use std::io::{self, IsTerminal, Write};
 use std::path::Path;
+use std::str::FromStr;
 
+pub mod ast;
+mod batch;
+pub mod cst;
 mod errors;
+mod format;
 mod lexer;
+mod outline;
+mod parser;
+mod semantic;
 
-pub use lexer::{KwKind, Lexer, LitKind, Token, TokenKind};
+pub use annotate_snippets::snippet::AnnotationType;
+pub use batch::{parse_dir, ScriptResult};
+pub use errors::{codes, Diagnostic, Label, ParseGameError, SessionError};
+pub use format::Formatter;
+pub use lexer::{
+    split_namespace, tokenize_str, Checkpoint, Edit, KwKind, Lexer, LitKind, Token, TokenKind,
+    TokenStream,
+};
+pub use outline::{Symbol, SymbolKind};
+pub use parser::{PResult, ParseError, Parser};
+pub use semantic::{SemanticKind, SemanticToken};
+
+/// Line endings used by a source.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum LineEnding {
+    /// Only `\n`, also used for sources without any line break.
+    Lf,
+    /// Only `\r\n`.
+    CrLf,
+    /// Both `\n` and `\r\n`.
+    Mixed,
+}
+
+/// Whether pretty-printed diagnostics use ANSI colors.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ColorChoice {
+    /// Colors when stderr is a terminal and the `NO_COLOR` environment variable is not set.
+    Auto,
+    Always,
+    Never,
+}
 
 struct Source {
     pub filename: OsString,
     pub content: String,
+    // byte offset of the start of each line
+    line_starts: Vec<usize>,
+    // number of lines ending with "\r\n"
+    crlf_count: usize,
 }
 
 impl Source {
-    // (line number, column number)
-    fn lineno_from_offset(&self, mut offset: usize) -> (usize, usize) {
-        let mut content = &self.content.bytes().collect::<Vec<u8>>()[..];
-        let mut buf = String::new();
-        let mut length;
-        let mut line_num = 1;
-        loop {
-            content.read_line(&mut buf).unwrap();
-            length = buf.len();
-            if offset < length {
-                break;
-            }
-            offset -= length;
-            line_num += 1;
-            buf.clear();
+    fn new(filename: OsString, content: String) -> Self {
+        let mut line_starts = vec![0];
+        line_starts
+            .extend(content.bytes().enumerate().filter(|(_, b)| *b == b'\n').map(|(i, _)| i + 1));
+        let crlf_count = content.matches("\r\n").count();
+        Self { filename, content, line_starts, crlf_count }
+    }
+
+    fn line_ending(&self) -> LineEnding {
+        let lf_count = self.line_starts.len() - 1 - self.crlf_count;
+        match (lf_count, self.crlf_count) {
+            (_, 0) => LineEnding::Lf,
+            (0, _) => LineEnding::CrLf,
+            _ => LineEnding::Mixed,
         }
-        (line_num, offset)
     }
 
-    fn lines_from_linenos(&self, lo: usize, hi: usize) -> Vec<String> {
-        let mut content = &self.content.bytes().collect::<Vec<u8>>()[..];
-        let mut buf = String::new();
-        let mut line_num = 1;
-        let mut lines = vec![];
-        loop {
-            content.read_line(&mut buf).unwrap();
-            if lo <= line_num {
-                lines.push(buf.clone());
-            }
-            if line_num >= hi {
-                break;
+    /// Returns the 1-based line number and the 0-based column of `offset`.
+    ///
+    /// The column counts characters, not bytes, from the start of the line. A tab moves it to the
+    /// next multiple of `tab_width`, so a width of 1 counts tabs as any other character.
+    fn lineno_from_offset(&self, offset: usize, tab_width: usize) -> (usize, usize) {
+        // offsets of a lexer reading another input may be past the end
+        let offset = offset.min(self.content.len());
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next_line) => next_line - 1,
+        };
+        let line_bytes = &self.content.as_bytes()[self.line_starts[line]..offset];
+        // count every byte that starts a utf-8 character
+        let column = line_bytes.iter().filter(|b| (**b & 0xC0) != 0x80).fold(0, |column, b| {
+            if *b == b'\t' {
+                (column / tab_width + 1) * tab_width
+            } else {
+                column + 1
             }
-            line_num += 1;
-            buf.clear();
-        }
-        lines
+        });
+        (line + 1, column)
+    }
+
+    /// Returns the lines `lo..=hi`, 1-based and including their line endings.
+    ///
+    /// Lines past the end of the source are left out.
+    fn lines_from_linenos(&self, lo: usize, hi: usize) -> Vec<String> {
+        // the last line is empty when the source ends with a newline
+        let hi = hi.min(self.line_starts.len());
+        (lo.max(1)..=hi)
+            .map(|line| {
+                let start = self.line_starts[line - 1];
+                let end = self.line_starts.get(line).copied().unwrap_or(self.content.len());
+                self.content[start..end].to_string()
+            })
+            .collect()
     }
 }
 
-#[derive(PartialEq)]
+/// The game a script is written for, Skyrim by default since it has the most scripts around.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy, Default)]
 pub enum Game {
+    #[default]
     TESV,
     FO4,
 }
 
+impl FromStr for Game {
+    type Err = ParseGameError;
+
+    /// Accepts `tesv`, `skyrim`, `fo4` and `fallout4`, ignoring case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "tesv" | "skyrim" => Ok(Game::TESV),
+            "fo4" | "fallout4" => Ok(Game::FO4),
+            _ => Err(ParseGameError(s.to_string())),
+        }
+    }
+}
+
 pub struct ParserSession {
     src: Source,
     game: Game,
+    min_level: AnnotationType,
+    tab_width: usize,
+    max_ident_len: Option<usize>,
+    max_line_len: Option<usize>,
+    color: ColorChoice,
+    diagnostics: RefCell<Vec<Diagnostic>>,
+    output: RefCell<Option<Box<dyn Write + Send>>>,
 }
 
 impl ParserSession {
-    pub fn from_file(path: &str, game: Game) -> Result<Self, String> {
-        let path = Path::new(path);
+    pub fn from_file<P: AsRef<Path>>(path: P, game: Game) -> Result<Self, SessionError> {
+        let path = path.as_ref();
         if !path.is_file() {
-            return Err("Path is not a file.".to_string());
+            return Err(SessionError::NotAFile(path.to_path_buf()));
         }
-        let filename = path.file_name().expect("Could not find file name.").to_owned();
-        let content = fs::read_to_string(path).map_err(|x| format!("{}", x))?;
-        let src = Source { filename, content };
-        Ok(Self { src, game })
+        let filename = match path.file_name() {
+            Some(filename) => filename.to_owned(),
+            None => return Err(SessionError::NoFileName(path.to_path_buf())),
+        };
+        let content = fs::read_to_string(path)?;
+        let src = Source::new(filename, content);
+        Ok(Self::new(src, game))
     }
 
     pub fn from_string(script: &str, game: Game) -> Self {
         let filename = OsString::from("<stdin>");
-        let src = Source { filename, content: script.to_string() };
-        Self { src, game }
+        let src = Source::new(filename, script.to_string());
+        Self::new(src, game)
+    }
+
+    fn new(src: Source, game: Game) -> Self {
+        Self {
+            src,
+            game,
+            min_level: AnnotationType::Help,
+            tab_width: 1,
+            // names are stored in the compiled script's string table with a 16-bit length
+            max_ident_len: Some(u16::MAX as usize),
+            max_line_len: None,
+            color: ColorChoice::Auto,
+            diagnostics: RefCell::new(vec![]),
+            output: RefCell::new(None),
+        }
+    }
+
+    /// Drops emitted diagnostics less severe than `level`, e.g. `Error` to ignore warnings.
+    ///
+    /// Fatal errors are always kept. Nothing is dropped by default.
+    pub fn with_min_level(mut self, level: AnnotationType) -> Self {
+        self.min_level = level;
+        self
+    }
+
+    /// Expands tabs to the next multiple of `width` columns in `location`, like editors do.
+    ///
+    /// Tabs count as a single column by default. A width of 0 is treated as 1.
+    pub fn with_tab_width(mut self, width: usize) -> Self {
+        self.tab_width = width.max(1);
+        self
+    }
+
+    /// Warns about identifiers longer than `len` bytes, which the game compiler may reject.
+    ///
+    /// Defaults to 65535, the longest string a compiled `.pex` script can hold.
+    pub fn with_max_ident_len(mut self, len: usize) -> Self {
+        self.max_ident_len = Some(len);
+        self
+    }
+
+    /// Warns about lines longer than `len` bytes, excluding the line break.
+    ///
+    /// Lines joined by a line continuation count as one. No limit is enforced by default, as the
+    /// compilers document none.
+    pub fn with_max_line_len(mut self, len: usize) -> Self {
+        self.max_line_len = Some(len);
+        self
+    }
+
+    /// Whether diagnostics are pretty-printed to stderr as they are emitted, off by default.
+    pub fn set_print_diagnostics(&mut self, print: bool) {
+        *self.output.get_mut() = if print { Some(Box::new(io::stderr())) } else { None };
+    }
+
+    /// Whether pretty-printed diagnostics use colors, `ColorChoice::Auto` by default.
+    pub fn set_color(&mut self, color: ColorChoice) {
+        self.color = color;
+    }
+
+    /// Pretty-prints diagnostics to `output` as they are emitted instead of stderr.
+    pub fn set_output(&mut self, output: Box<dyn Write + Send>) {
+        *self.output.get_mut() = Some(output);
+    }
+
+    /// Every diagnostic emitted so far, in emission order.
+    pub fn diagnostics(&self) -> Ref<'_, [Diagnostic]> {
+        Ref::map(self.diagnostics.borrow(), |diagnostics| &diagnostics[..])
+    }
+
+    /// Whether a fatal diagnostic has been emitted.
+    ///
+    /// A fatal error stops the lexer, so any tokens or AST produced afterwards are incomplete.
+    pub fn has_fatal(&self) -> bool {
+        self.diagnostics.borrow().iter().any(|diagnostic| diagnostic.fatal)
+    }
+
+    /// The line endings used by the source.
+    pub fn line_ending(&self) -> LineEnding {
+        self.src.line_ending()
+    }
+
+    /// Rewrites a source with mixed line endings to only use the most common one, LF on a tie.
+    ///
+    /// Must be called before lexing since every offset after a changed line ending moves.
+    pub fn normalize_line_endings(&mut self) {
+        if self.src.line_ending() != LineEnding::Mixed {
+            return;
+        }
+        let lf_count = self.src.line_starts.len() - 1 - self.src.crlf_count;
+        let content = self.src.content.replace("\r\n", "\n");
+        let content =
+            if self.src.crlf_count > lf_count { content.replace('\n', "\r\n") } else { content };
+        let filename = std::mem::take(&mut self.src.filename);
+        self.src = Source::new(filename, content);
+    }
+
+    /// Returns the line and column of the byte `offset` into the source.
+    ///
+    /// Lines are 1-based and columns are 0-based character counts, as shown in diagnostics, with
+    /// tabs expanded according to `with_tab_width`.
+    pub fn location(&self, offset: usize) -> (usize, usize) {
+        self.src.lineno_from_offset(offset, self.tab_width)
+    }
+
+    /// Returns the `location` of both ends of the byte range `lo..hi`.
+    pub fn line_range(&self, lo: usize, hi: usize) -> ((usize, usize), (usize, usize)) {
+        (self.location(lo), self.location(hi))
+    }
+
+    /// Lexes the whole source and returns every token, including the trailing `Eof`.
+    ///
+    /// ```
+    /// use libpapyrus::{Game, ParserSession, TokenKind};
+    ///
+    /// let tokens = ParserSession::from_string("Int x", Game::TESV).tokenize();
+    /// assert_eq!(tokens.len(), 4);
+    /// assert_eq!(tokens.last().unwrap().kind, TokenKind::Eof);
+    /// ```
+    pub fn tokenize(&self) -> Vec<Token> {
+        Lexer::from_sess(self).collect()
+    }
+
+    /// Returns the token under the byte `offset`, such as the one at an editor cursor.
+    ///
+    /// When `offset` is between two tokens and only the one ending there is significant, that one
+    /// is returned, so a cursor right after an identifier finds it. The end of the source gives
+    /// the `Eof` token and offsets past it give `None`.
+    pub fn token_at_offset(&self, offset: usize) -> Option<Token> {
+        if offset > self.src.content.len() {
+            return None;
+        }
+        let mut tokens = self.tokenize();
+        // the last token is always `Eof`, which ends at the end of the source
+        let index = tokens.iter().position(|token| offset < token.hi()).unwrap_or(tokens.len() - 1);
+        let is_significant = |token: &Token| !token.is_trivia() && token.kind != TokenKind::Eof;
+        if index > 0
+            && tokens[index - 1].hi() == offset
+            && is_significant(&tokens[index - 1])
+            && !is_significant(&tokens[index])
+        {
+            return Some(tokens.swap_remove(index - 1));
+        }
+        Some(tokens.swap_remove(index))
+    }
+
+    /// Lexes the source again after `edit`, given the tokens `old` of the source before it.
+    ///
+    /// Only the tokens around the edit are lexed again, the others are reused with their offsets
+    /// moved, so the result is the same as `tokenize` at a fraction of the cost. Diagnostics are
+    /// only emitted for the part lexed again.
+    pub fn retokenize(&self, old: &[Token], edit: Edit) -> Vec<Token> {
+        lexer::relex(Lexer::from_sess(self), old, edit)
+    }
+
+    /// The whole source being parsed.
+    pub fn source_text(&self) -> &str {
+        &self.src.content
+    }
+
+    /// The source text of the byte range `lo..hi`.
+    ///
+    /// Returns an empty string if the range is out of bounds or does not fall on character
+    /// boundaries.
+    pub fn span_text(&self, lo: usize, hi: usize) -> &str {
+        self.src.content.get(lo..hi).unwrap_or_default()
+    }
+
+    /// Returns the source text a literal token was lexed from, or `None` for other tokens.
+    ///
+    /// Literal values lose their notation, so this is how to recover e.g. `0x0001` from a
+    /// `LitKind::Integer(1, true)`.
+    pub fn literal_text(&self, token: &Token) -> Option<&str> {
+        match token.kind {
+            TokenKind::Literal(_) => Some(self.span_text(token.lo(), token.hi())),
+            _ => None,
+        }
+    }
+
+    /// Classifies every token for syntax highlighting.
+    pub fn semantic_tokens(&self) -> Vec<SemanticToken> {
+        semantic::classify(&self.tokenize())
+    }
+
+    /// Parses the script and returns its declarations for an editor outline.
+    ///
+    /// Returns nothing if the script header cannot be parsed.
+    pub fn outline(&self) -> Vec<Symbol> {
+        match Parser::from_sess(self).parse_script() {
+            Ok(script) => outline::outline(&script),
+            Err(ParseError) => vec![],
+        }
+    }
+
+    pub fn new_error(&self) -> errors::ErrorBuilder<'_> {
+        errors::ErrorBuilder::new(self, self.use_colors())
+    }
+
+    fn use_colors(&self) -> bool {
+        match self.color {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            // https://no-color.org asks to ignore an empty NO_COLOR
+            ColorChoice::Auto => {
+                env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+                    && io::stderr().is_terminal()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn from_file() {
+        let path = env::temp_dir().join("libpapyrus_from_file.psc");
+        fs::write(&path, "ScriptName libpapyrus_from_file").unwrap();
+        let sess = ParserSession::from_file(&path, Game::TESV).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!("libpapyrus_from_file.psc", sess.src.filename);
+        assert_eq!("ScriptName libpapyrus_from_file", sess.src.content);
+    }
+
+    #[test]
+    fn from_file_not_a_file() {
+        let path = env::temp_dir();
+        match ParserSession::from_file(&path, Game::TESV) {
+            Err(SessionError::NotAFile(err_path)) => assert_eq!(path, err_path),
+            _ => panic!("expected SessionError::NotAFile"),
+        }
+    }
+
+    #[test]
+    fn token_at_offset() {
+        let sess = ParserSession::from_string("x = Foo(1) ; call", Game::TESV);
+        let kind_at = |offset| sess.token_at_offset(offset).map(|token| token.kind);
+        assert_eq!(Some(TokenKind::Ident("x".to_string())), kind_at(0));
+        assert_eq!(Some(TokenKind::Ident("x".to_string())), kind_at(1));
+        assert_eq!(Some(TokenKind::Equal), kind_at(3));
+        assert_eq!(Some(TokenKind::Ident("Foo".to_string())), kind_at(5));
+        assert_eq!(Some(TokenKind::LParen), kind_at(7));
+        assert_eq!(Some(TokenKind::Literal(LitKind::Integer(1, false))), kind_at(8));
+        assert_eq!(Some(TokenKind::Comment(" call".to_string())), kind_at(16));
+        assert_eq!(Some(TokenKind::Eof), kind_at(17));
+        assert_eq!(None, kind_at(18));
+    }
+
+    #[test]
+    fn game_hash() {
+        let mut extensions = HashMap::new();
+        extensions.insert(Game::TESV, "pex");
+        extensions.insert(Game::default(), "psc");
+        extensions.insert(Game::FO4, "pex");
+        assert_eq!(2, extensions.len());
+        assert_eq!(Some(&"psc"), extensions.get(&Game::TESV));
+    }
+
+    #[test]
+    fn span_text() {
+        let sess = ParserSession::from_string("Int café = 1", Game::TESV);
+        assert_eq!("Int café = 1", sess.source_text());
+        let tokens = sess.tokenize();
+        assert_eq!(TokenKind::Ident("caf".to_string()), tokens[2].kind);
+        assert_eq!("caf", sess.span_text(tokens[2].lo(), tokens[2].hi()));
+        assert_eq!("café", sess.span_text(4, 9));
+        assert_eq!("", sess.span_text(4, 8));
+        assert_eq!("", sess.span_text(10, 100));
+    }
+
+    #[test]
+    fn literal_text() {
+        let sess = ParserSession::from_string("Int x = 0x0001", Game::TESV);
+        let tokens = sess.tokenize();
+        assert_eq!(TokenKind::Literal(LitKind::Integer(1, true)), tokens[6].kind);
+        assert_eq!(Some("0x0001"), sess.literal_text(&tokens[6]));
+        assert_eq!(None, sess.literal_text(&tokens[2]));
+    }
+
+    #[test]
+    fn game_from_str() {
+        assert_eq!(Ok(Game::TESV), "tesv".parse());
+        assert_eq!(Ok(Game::TESV), "Skyrim".parse());
+        assert_eq!(Ok(Game::FO4), "FO4".parse());
+        assert_eq!(Ok(Game::FO4), "fallout4".parse());
+        assert_eq!(Err(ParseGameError("oblivion".to_string())), "oblivion".parse::<Game>());
+    }
+
+    #[test]
+    fn lineno_from_offset() {
+        let src = Source::new(OsString::from("<stdin>"), "ab\ncd\r\n\nef".to_string());
+        assert_eq!((1, 0), src.lineno_from_offset(0, 1));
+        assert_eq!((1, 2), src.lineno_from_offset(2, 1));
+        assert_eq!((2, 0), src.lineno_from_offset(3, 1));
+        assert_eq!((2, 3), src.lineno_from_offset(6, 1));
+        assert_eq!((3, 0), src.lineno_from_offset(7, 1));
+        assert_eq!((4, 1), src.lineno_from_offset(9, 1));
+        assert_eq!((4, 2), src.lineno_from_offset(10, 1));
+    }
+
+    #[test]
+    fn lines_from_linenos() {
+        let src = Source::new(OsString::from("<stdin>"), "a\nb\r\nc\n".to_string());
+        assert_eq!(vec!["b\r\n", "c\n"], src.lines_from_linenos(2, 3));
+        assert_eq!(vec!["c\n", ""], src.lines_from_linenos(3, 10));
+        assert!(src.lines_from_linenos(5, 10).is_empty());
+        let src = Source::new(OsString::from("<stdin>"), "é\r\n\nx".to_string());
+        assert_eq!(vec!["é\r\n", "\n", "x"], src.lines_from_linenos(1, 3));
+    }
+
+    #[test]
+    fn line_ending() {
+        assert_eq!(LineEnding::Lf, ParserSession::from_string("a", Game::TESV).line_ending());
+        assert_eq!(LineEnding::Lf, ParserSession::from_string("a\nb\n", Game::TESV).line_ending());
+        let sess = ParserSession::from_string("a\r\nb\r\n", Game::TESV);
+        assert_eq!(LineEnding::CrLf, sess.line_ending());
+
+        let mut sess = ParserSession::from_string("a\r\nb\nc\r\n", Game::TESV);
+        assert_eq!(LineEnding::Mixed, sess.line_ending());
+        sess.normalize_line_endings();
+        assert_eq!(LineEnding::CrLf, sess.line_ending());
+        assert_eq!("a\r\nb\r\nc\r\n", sess.src.content);
+    }
+
+    #[test]
+    fn location() {
+        let sess = ParserSession::from_string("Int x\n\nFoo(\"é\", y)\n", Game::TESV);
+        assert_eq!((1, 0), sess.location(0));
+        assert_eq!((1, 4), sess.location(4));
+        assert_eq!((2, 0), sess.location(6));
+        assert_eq!((3, 0), sess.location(7));
+        assert_eq!((3, 8), sess.location(16));
+        assert_eq!(((1, 4), (3, 3)), sess.line_range(4, 10));
+    }
+
+    #[test]
+    fn color() {
+        let mut sess = ParserSession::from_string("a & b", Game::TESV);
+        sess.set_color(ColorChoice::Never);
+        let mut output = Vec::new();
+        sess.new_error().warning("single ampersand").span(2, 3).emit_to(&mut output).unwrap();
+        assert!(!String::from_utf8(output).unwrap().contains('\x1b'));
+
+        sess.set_color(ColorChoice::Always);
+        let mut output = Vec::new();
+        sess.new_error().warning("single ampersand").span(2, 3).emit_to(&mut output).unwrap();
+        assert!(String::from_utf8(output).unwrap().contains('\x1b'));
+    }
+
+    #[test]
+    fn tab_width() {
+        let script = "Event OnInit()\n\t\tx = 1\n  \ty = 2";
+        let sess = ParserSession::from_string(script, Game::TESV);
+        assert_eq!((2, 2), sess.location(17));
+        let sess = ParserSession::from_string(script, Game::TESV).with_tab_width(4);
+        assert_eq!((2, 8), sess.location(17));
+        assert_eq!((3, 4), sess.location(26));
     }
 
-    pub fn new_error(&self) -> errors::ErrorBuilder {
-        errors::ErrorBuilder::new(self, true)
+    #[test]
+    fn lineno_from_offset_unicode() {
+        let src = Source::new(OsString::from("<stdin>"), "\"café\" x\né y".to_string());
+        assert_eq!((1, 7), src.lineno_from_offset(8, 1));
+        assert_eq!((2, 2), src.lineno_from_offset(13, 1));
     }
 }
@@ -0,0 +1,143 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::ast::{
+    walk_group, walk_state, CustomEvent, Event, Function, Group, Property, Script, State, Struct,
+    VarDecl, Visitor,
+};
+
+/// Kind of a declaration in the outline, modelled after LSP symbol kinds.
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum SymbolKind {
+    Script,
+    CustomEvent,
+    Property,
+    Group,
+    Function,
+    Event,
+    State,
+    Struct,
+    Field,
+    Variable,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub span: (usize, usize),
+    /// Declarations nested inside this one, such as the functions of a state.
+    pub children: Vec<Symbol>,
+}
+
+impl Symbol {
+    fn new(name: &str, kind: SymbolKind, span: (usize, usize)) -> Self {
+        Self { name: name.to_string(), kind, span, children: vec![] }
+    }
+}
+
+struct Outline {
+    // symbols of the declaration being visited and of each enclosing one
+    stack: Vec<Vec<Symbol>>,
+}
+
+impl Outline {
+    fn push(&mut self, symbol: Symbol) {
+        self.stack.last_mut().unwrap().push(symbol);
+    }
+
+    // visits the children of a declaration through `walk` and nests them under `symbol`
+    fn nest(&mut self, mut symbol: Symbol, walk: impl FnOnce(&mut Self)) {
+        self.stack.push(vec![]);
+        walk(self);
+        symbol.children = self.stack.pop().unwrap();
+        self.push(symbol);
+    }
+}
+
+impl Visitor for Outline {
+    fn visit_custom_event(&mut self, event: &CustomEvent) {
+        self.push(Symbol::new(&event.name, SymbolKind::CustomEvent, event.span));
+    }
+
+    fn visit_property(&mut self, property: &Property) {
+        self.push(Symbol::new(&property.name, SymbolKind::Property, property.span));
+    }
+
+    fn visit_group(&mut self, group: &Group) {
+        let symbol = Symbol::new(&group.name, SymbolKind::Group, group.span);
+        self.nest(symbol, |outline| walk_group(outline, group));
+    }
+
+    fn visit_function(&mut self, function: &Function) {
+        self.push(Symbol::new(&function.name, SymbolKind::Function, function.span));
+    }
+
+    fn visit_event(&mut self, event: &Event) {
+        self.push(Symbol::new(&event.name, SymbolKind::Event, event.span));
+    }
+
+    fn visit_state(&mut self, state: &State) {
+        let symbol = Symbol::new(&state.name, SymbolKind::State, state.span);
+        self.nest(symbol, |outline| walk_state(outline, state));
+    }
+
+    fn visit_struct(&mut self, item: &Struct) {
+        let mut symbol = Symbol::new(&item.name, SymbolKind::Struct, item.span);
+        symbol.children = item
+            .fields
+            .iter()
+            .map(|field| Symbol::new(&field.name, SymbolKind::Field, field.span))
+            .collect();
+        self.push(symbol);
+    }
+
+    fn visit_var_decl(&mut self, decl: &VarDecl) {
+        self.push(Symbol::new(&decl.name, SymbolKind::Variable, decl.span));
+    }
+}
+
+/// The script name followed by every top-level declaration of `script`.
+pub(crate) fn outline(script: &Script) -> Vec<Symbol> {
+    let header = &script.header;
+    let mut outline = Outline { stack: vec![vec![]] };
+    outline.push(Symbol::new(&header.name, SymbolKind::Script, header.span));
+    outline.visit_script(script);
+    outline.stack.pop().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Game, ParserSession};
+
+    #[test]
+    fn outline() {
+        let script = "ScriptName Foo\n\
+                      Int Property Count Auto\n\
+                      Function A()\n\
+                      EndFunction\n\
+                      State Waiting\n\
+                      \tFunction B()\n\
+                      \tEndFunction\n\
+                      EndState";
+        let sess = ParserSession::from_string(script, Game::TESV);
+        let outline = sess.outline();
+        let symbols = outline
+            .iter()
+            .map(|symbol| (symbol.name.as_str(), symbol.kind, symbol.span))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ("Foo", SymbolKind::Script, (0, 14)),
+                ("Count", SymbolKind::Property, (15, 38)),
+                ("A", SymbolKind::Function, (39, 63)),
+                ("Waiting", SymbolKind::State, (64, 113)),
+            ],
+            symbols
+        );
+        assert_eq!(vec![Symbol::new("B", SymbolKind::Function, (79, 104))], outline[3].children);
+    }
+}
@@ -0,0 +1,2753 @@
+use crate::ast::{
+    Arg, AssignOp, BinOp, CustomEvent, Event, Expr, FnFlag, Function, Group, IfArm, Import, Member,
+    Param, Property, PropertyFlag, PropertyKind, Script, ScriptFlag, ScriptHeader, State,
+    StateMember, Stmt, Struct, Type, UnOp, VarDecl, VarFlag,
+};
+use std::collections::HashMap;
+use std::path::Path;
+
+use annotate_snippets::snippet::AnnotationType;
+
+use crate::errors::codes;
+use crate::lexer::{split_namespace, KwKind, Lexer, LitKind, Token, TokenKind, TokenStream};
+use crate::{Game, ParserSession};
+
+/// Marker returned when parsing fails.
+///
+/// The diagnostic describing the failure has already been emitted through the session by the time
+/// this is returned.
+#[derive(PartialEq, Debug)]
+pub struct ParseError;
+
+pub type PResult<T> = Result<T, ParseError>;
+
+pub struct Parser<'a> {
+    sess: &'a ParserSession,
+    tokens: TokenStream<'a>,
+    token: Token,
+    // end of the last token consumed by bump
+    prev_hi: usize,
+    // whether a newline was skipped right before the current token
+    newline_before: bool,
+    // last documentation block skipped right before the current token
+    doc: Option<Token>,
+    // whether the current token is negated by a unary '-', which lets 2147483648 through
+    negated: bool,
+}
+
+impl<'a> Parser<'a> {
+    pub fn from_sess(sess: &'a ParserSession) -> Self {
+        let mut tokens = TokenStream::new(Lexer::from_sess(sess));
+        let newline_before = tokens.newline_before(0);
+        let doc = tokens.doc_before(0).cloned();
+        let token = tokens.bump();
+        Self { sess, tokens, token, prev_hi: 0, newline_before, doc, negated: false }
+    }
+
+    fn bump(&mut self) -> Token {
+        self.newline_before = self.tokens.newline_before(0);
+        // only declaration headers take the documentation block after them
+        if let Some(doc) = std::mem::replace(&mut self.doc, self.tokens.doc_before(0).cloned()) {
+            self.unattached_doc(&doc);
+        }
+        self.prev_hi = self.token.hi();
+        let next = self.tokens.bump();
+        std::mem::replace(&mut self.token, next)
+    }
+
+    fn unattached_doc(&self, doc: &Token) {
+        if self.sess.has_fatal() {
+            return;
+        }
+        self.sess
+            .new_error()
+            .warning("documentation block is not attached to anything")
+            .span(doc.lo(), doc.hi())
+            .label_warning("expected right after a declaration header")
+            .emit();
+    }
+
+    // documentation block right before the current token, which follows a declaration header when
+    // called at the end of its line
+    fn take_doc(&mut self) -> Option<String> {
+        self.doc.take().map(|doc| match doc.kind {
+            TokenKind::Doc(text) => text,
+            _ => unreachable!(),
+        })
+    }
+
+    // kind of the `n`th token after the current one, or `Eof` if it is on a later line
+    fn look_ahead(&mut self, n: usize) -> &TokenKind {
+        if (0..n).any(|i| self.tokens.newline_before(i)) {
+            return &TokenKind::Eof;
+        }
+        &self.tokens.peek_nth(n - 1).kind
+    }
+
+    fn at_eol(&self) -> bool {
+        self.newline_before || self.token.kind == TokenKind::Eof
+    }
+
+    fn expect_eol(&self) -> PResult<()> {
+        if self.at_eol() {
+            Ok(())
+        } else {
+            Err(self.unexpected("expected end of line"))
+        }
+    }
+
+    fn check(&self, kind: TokenKind) -> bool {
+        self.token.kind == kind
+    }
+
+    fn eat(&mut self, kind: TokenKind) -> bool {
+        if self.check(kind) {
+            self.bump();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, kind: TokenKind, expected: &str) -> PResult<Token> {
+        if self.check(kind) {
+            Ok(self.bump())
+        } else {
+            Err(self.unexpected(expected))
+        }
+    }
+
+    fn check_keyword(&self, kw: KwKind) -> bool {
+        self.token.kind == TokenKind::Keyword(kw)
+    }
+
+    fn eat_keyword(&mut self, kw: KwKind) -> bool {
+        if self.check_keyword(kw) {
+            self.bump();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_keyword(&mut self, kw: KwKind, expected: &str) -> PResult<Token> {
+        if self.check_keyword(kw) {
+            Ok(self.bump())
+        } else {
+            Err(self.unexpected(expected))
+        }
+    }
+
+    fn expect_ident(&mut self) -> PResult<String> {
+        match &self.token.kind {
+            TokenKind::Ident(_) => match self.bump().kind {
+                TokenKind::Ident(name) => Ok(name),
+                _ => unreachable!(),
+            },
+            _ => Err(self.unexpected("expected identifier")),
+        }
+    }
+
+    fn unexpected(&self, expected: &str) -> ParseError {
+        let label = match self.token.kind {
+            TokenKind::Eof => "unexpected end of file",
+            // already reported by the lexer
+            TokenKind::Unknown => return ParseError,
+            _ => "unexpected token",
+        };
+        self.error(expected, self.token.lo(), self.token.hi(), label);
+        ParseError
+    }
+
+    fn error(&self, title: &str, lo: usize, hi: usize, label: &str) {
+        // a fatal lexer error truncates the token stream, anything reported after it is noise
+        if self.sess.has_fatal() {
+            return;
+        }
+        self.sess.new_error().error(title).span(lo, hi).label_error(label).emit();
+    }
+
+    // FO4-only keywords lex as identifiers in other games
+    fn fo4_only(&self, what: &str) -> ParseError {
+        self.error(
+            &format!("{} are only available in Fallout 4", what),
+            self.token.lo(),
+            self.token.hi(),
+            "not supported by this game",
+        );
+        ParseError
+    }
+
+    // a Fallout 4 flag on a declaration it does not apply to, skipped without failing the parse
+    fn misplaced_flag(&mut self, decl: &str, allowed_on: &str) {
+        let flag = self.bump();
+        self.error(
+            &format!("'{}' is not a valid {} flag", flag.kind, decl),
+            flag.lo(),
+            flag.hi(),
+            &format!("only valid on {}", allowed_on),
+        );
+    }
+
+    fn check_ident(&self, name: &str) -> bool {
+        match &self.token.kind {
+            TokenKind::Ident(ident) => ident.eq_ignore_ascii_case(name),
+            _ => false,
+        }
+    }
+
+    pub fn parse_type(&mut self) -> PResult<Type> {
+        let ty = self.parse_base_type()?;
+        if !self.eat(TokenKind::LSquare) {
+            return Ok(ty);
+        }
+        self.expect(TokenKind::RSquare, "expected ']' to close array type")?;
+        // report nested arrays but keep parsing them as a single array
+        if !self.at_eol() && self.check(TokenKind::LSquare) {
+            let lo = self.token.lo();
+            while !self.at_eol() && self.eat(TokenKind::LSquare) {
+                self.expect(TokenKind::RSquare, "expected ']' to close array type")?;
+            }
+            self.error(
+                "arrays of arrays are not supported",
+                lo,
+                self.prev_hi,
+                "arrays can only have one dimension",
+            );
+        }
+        Ok(Type::Array(Box::new(ty)))
+    }
+
+    fn parse_base_type(&mut self) -> PResult<Type> {
+        let ty = match &self.token.kind {
+            TokenKind::Keyword(KwKind::Bool) => Type::Bool,
+            TokenKind::Keyword(KwKind::Float) => Type::Float,
+            TokenKind::Keyword(KwKind::Int) => Type::Int,
+            TokenKind::Keyword(KwKind::String) => Type::String,
+            TokenKind::Keyword(KwKind::Var) => Type::Var,
+            TokenKind::Keyword(KwKind::CustomEventName) => Type::CustomEventName,
+            TokenKind::Keyword(KwKind::ScriptEventName) => Type::ScriptEventName,
+            TokenKind::Ident(name) => Type::Object(name.clone()),
+            _ => return Err(self.unexpected("expected type")),
+        };
+        self.bump();
+        Ok(ty)
+    }
+
+    // a literal where an expression is not allowed, which may still be a negative number
+    fn parse_literal(&mut self) -> PResult<Expr> {
+        let lo = self.token.lo();
+        let negative = self.eat(TokenKind::Minus);
+        let negated = std::mem::take(&mut self.negated);
+        let lit = match self.token.kind {
+            TokenKind::Literal(_) => match self.bump().kind {
+                TokenKind::Literal(lit) => lit,
+                _ => unreachable!(),
+            },
+            _ if negative => return Err(self.unexpected("expected number after '-'")),
+            _ => return Err(self.unexpected("expected literal")),
+        };
+        let lit = match lit {
+            // 2147483648, which only fits in an i32 once negated
+            LitKind::Integer(i32::MIN, false) if !negative && !negated => {
+                self.sess
+                    .new_error()
+                    .error("could not parse integer literal")
+                    .code(codes::INVALID_INTEGER)
+                    .span(lo, self.prev_hi)
+                    .label_help("try using a smaller integer")
+                    .emit();
+                return Err(ParseError);
+            }
+            lit if !negative => lit,
+            LitKind::Integer(value, is_hex) => LitKind::Integer(value.wrapping_neg(), is_hex),
+            LitKind::Float(value, raw) => LitKind::Float(-value, format!("-{}", raw)),
+            _ => {
+                self.error("only numbers can be negative", lo, self.prev_hi, "not a number");
+                return Err(ParseError);
+            }
+        };
+        Ok(Expr::Literal { lit, span: (lo, self.prev_hi) })
+    }
+
+    fn unterminated(&self, opener: (usize, usize), closer: &str) -> ParseError {
+        self.error(
+            &format!("expected '{}' before end of file", closer),
+            opener.0,
+            opener.1,
+            "block is never closed",
+        );
+        ParseError
+    }
+
+    // parses statements until one of `terminators`, which is left unconsumed
+    fn parse_block(
+        &mut self,
+        terminators: &[KwKind],
+        opener: (usize, usize),
+        closer: &str,
+    ) -> PResult<Vec<Stmt>> {
+        let mut stmts = vec![];
+        loop {
+            match &self.token.kind {
+                TokenKind::Keyword(kw) if terminators.contains(kw) => break,
+                TokenKind::Eof => return Err(self.unterminated(opener, closer)),
+                _ => stmts.extend(self.recover(Self::parse_stmt)),
+            }
+        }
+        Ok(stmts)
+    }
+
+    // a declaration starts with a type, `Foo x` and `Foo[] x` are told apart from expressions by
+    // the identifier that follows
+    fn is_var_decl_start(&mut self) -> bool {
+        match self.token.kind {
+            TokenKind::Keyword(KwKind::Bool)
+            | TokenKind::Keyword(KwKind::Float)
+            | TokenKind::Keyword(KwKind::Int)
+            | TokenKind::Keyword(KwKind::String)
+            | TokenKind::Keyword(KwKind::Var) => true,
+            TokenKind::Ident(_) => match self.look_ahead(1) {
+                TokenKind::Ident(_) => true,
+                TokenKind::LSquare => {
+                    *self.look_ahead(2) == TokenKind::RSquare
+                        && matches!(self.look_ahead(3), TokenKind::Ident(_))
+                }
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    pub fn parse_var_decl(&mut self) -> PResult<VarDecl> {
+        let lo = self.token.lo();
+        let ty = self.parse_type()?;
+        let name = self.expect_ident()?;
+        let init = if self.eat(TokenKind::Equal) { Some(self.parse_expr()?) } else { None };
+        let mut flags = vec![];
+        while !self.at_eol() {
+            let flag = match self.token.kind {
+                TokenKind::Keyword(KwKind::Const) => VarFlag::Const,
+                TokenKind::Keyword(KwKind::BetaOnly) | TokenKind::Keyword(KwKind::DebugOnly) => {
+                    self.misplaced_flag("variable", "functions");
+                    continue;
+                }
+                _ if self.check_ident("conditional") => VarFlag::Conditional,
+                _ if self.check_ident("const") => return Err(self.fo4_only("const variables")),
+                _ => return Err(self.unexpected("expected variable flag or end of line")),
+            };
+            self.bump();
+            flags.push(flag);
+        }
+        Ok(VarDecl { ty, name, init, flags, span: (lo, self.prev_hi) })
+    }
+
+    fn parse_stmt(&mut self) -> PResult<Stmt> {
+        match self.token.kind {
+            TokenKind::Keyword(KwKind::If) => return self.parse_if(),
+            TokenKind::Keyword(KwKind::While) => return self.parse_while(),
+            TokenKind::Keyword(KwKind::Return) => return self.parse_return(),
+            _ => (),
+        }
+        if self.is_var_decl_start() {
+            return Ok(Stmt::Var(self.parse_var_decl()?));
+        }
+        let expr = self.parse_expr()?;
+        if let Some(op) = self.assign_op() {
+            return self.parse_assign(expr, op);
+        }
+        self.expect_eol()?;
+        Ok(Stmt::Expr(expr))
+    }
+
+    fn assign_op(&self) -> Option<AssignOp> {
+        if self.at_eol() {
+            return None;
+        }
+        let op = match self.token.kind {
+            TokenKind::Equal => AssignOp::Assign,
+            TokenKind::PlusEq => AssignOp::Add,
+            TokenKind::MinusEq => AssignOp::Sub,
+            TokenKind::MultiplyEq => AssignOp::Mul,
+            TokenKind::DivideEq => AssignOp::Div,
+            TokenKind::ModuleEq => AssignOp::Mod,
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    fn parse_assign(&mut self, target: Expr, op: AssignOp) -> PResult<Stmt> {
+        match target {
+            Expr::Ident { .. } | Expr::Member { .. } | Expr::Index { .. } => (),
+            _ => {
+                let (lo, hi) = target.span();
+                self.error("invalid assignment target", lo, hi, "cannot assign to this expression");
+            }
+        }
+        let op_token = self.bump();
+        self.expect_operand(&op_token)?;
+        let value = self.parse_expr()?;
+        let span = (target.span().0, value.span().1);
+        self.expect_eol()?;
+        Ok(Stmt::Assign { target, op, value, span })
+    }
+
+    pub fn parse_if(&mut self) -> PResult<Stmt> {
+        let if_token = self.expect_keyword(KwKind::If, "expected 'If'")?;
+        let terminators = [KwKind::ElseIf, KwKind::Else, KwKind::EndIf];
+        let mut arms = vec![];
+        let mut arm_lo = if_token.lo();
+        loop {
+            let cond = self.parse_condition()?;
+            self.expect_eol()?;
+            let body = self.parse_block(&terminators, if_token.span(), "EndIf")?;
+            arms.push(IfArm { cond, body, span: (arm_lo, self.prev_hi) });
+            if !self.check_keyword(KwKind::ElseIf) {
+                break;
+            }
+            arm_lo = self.bump().lo();
+        }
+        let else_block = if self.eat_keyword(KwKind::Else) {
+            self.expect_eol()?;
+            Some(self.parse_block(&[KwKind::EndIf], if_token.span(), "EndIf")?)
+        } else {
+            None
+        };
+        self.expect_keyword(KwKind::EndIf, "expected 'EndIf'")?;
+        let span = (if_token.lo(), self.prev_hi);
+        self.expect_eol()?;
+        Ok(Stmt::If { arms, else_block, span })
+    }
+
+    // the condition of an `If`, `ElseIf` or `While`, where `=` is most likely a typo for `==`
+    fn parse_condition(&mut self) -> PResult<Expr> {
+        let lhs = self.parse_expr()?;
+        if self.at_eol() || !self.check(TokenKind::Equal) {
+            return Ok(lhs);
+        }
+        let eq_token = self.bump();
+        if !self.sess.has_fatal() {
+            self.sess
+                .new_error()
+                .error("expected a condition, found an assignment")
+                .span(eq_token.lo(), eq_token.hi())
+                .label_help("use '==' to compare values")
+                .emit();
+        }
+        // parsed as a comparison so the rest of the block is still checked
+        let rhs = self.parse_expr()?;
+        let span = (lhs.span().0, rhs.span().1);
+        Ok(Expr::Binary { op: BinOp::Eq, lhs: Box::new(lhs), rhs: Box::new(rhs), span })
+    }
+
+    pub fn parse_return(&mut self) -> PResult<Stmt> {
+        let lo = self.expect_keyword(KwKind::Return, "expected 'Return'")?.lo();
+        let value = if self.at_eol() { None } else { Some(self.parse_expr()?) };
+        let span = (lo, self.prev_hi);
+        self.expect_eol()?;
+        Ok(Stmt::Return { value, span })
+    }
+
+    pub fn parse_while(&mut self) -> PResult<Stmt> {
+        let while_token = self.expect_keyword(KwKind::While, "expected 'While'")?;
+        let cond = self.parse_condition()?;
+        self.expect_eol()?;
+        let body = self.parse_block(&[KwKind::EndWhile], while_token.span(), "EndWhile")?;
+        self.bump();
+        let span = (while_token.lo(), self.prev_hi);
+        self.expect_eol()?;
+        Ok(Stmt::While { cond, body, span })
+    }
+
+    // binary operator at the current token along with its precedence, higher binds tighter
+    fn binary_op(&self) -> Option<(BinOp, u8)> {
+        if self.at_eol() {
+            return None;
+        }
+        let op = match self.token.kind {
+            TokenKind::Or => (BinOp::Or, 1),
+            TokenKind::And => (BinOp::And, 2),
+            TokenKind::CmpEQ => (BinOp::Eq, 3),
+            TokenKind::CmpNE => (BinOp::Ne, 3),
+            TokenKind::CmpLT => (BinOp::Lt, 3),
+            TokenKind::CmpLE => (BinOp::Le, 3),
+            TokenKind::CmpGT => (BinOp::Gt, 3),
+            TokenKind::CmpGE => (BinOp::Ge, 3),
+            TokenKind::Plus => (BinOp::Add, 4),
+            TokenKind::Minus => (BinOp::Sub, 4),
+            TokenKind::Multiply => (BinOp::Mul, 5),
+            TokenKind::Divide => (BinOp::Div, 5),
+            TokenKind::Modulo => (BinOp::Mod, 5),
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    // operands must be on the same line as their operator
+    fn expect_operand(&self, op_token: &Token) -> PResult<()> {
+        if self.at_eol() {
+            self.error(
+                "expected expression after operator",
+                op_token.lo(),
+                op_token.hi(),
+                "missing operand",
+            );
+            return Err(ParseError);
+        }
+        Ok(())
+    }
+
+    pub fn parse_expr(&mut self) -> PResult<Expr> {
+        self.parse_binary(1)
+    }
+
+    fn parse_binary(&mut self, min_prec: u8) -> PResult<Expr> {
+        let mut lhs = self.parse_unary()?;
+        loop {
+            // 'Is' binds like a comparison but takes a type on its right
+            if !self.at_eol() && (self.check_keyword(KwKind::Is) || self.check_ident("is")) {
+                if min_prec > 3 {
+                    break;
+                }
+                lhs = self.parse_type_check(lhs)?;
+                continue;
+            }
+            let (op, prec) = match self.binary_op() {
+                Some(op) if op.1 >= min_prec => op,
+                _ => break,
+            };
+            // `a < b < c` compares the bool `a < b` to `c`, which is never what was meant
+            let chained = prec == 3
+                && matches!(
+                    lhs,
+                    Expr::Binary {
+                        op: BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge,
+                        ..
+                    } | Expr::TypeCheck { .. }
+                );
+            let op_token = self.bump();
+            self.expect_operand(&op_token)?;
+            let rhs = self.parse_binary(prec + 1)?;
+            let span = (lhs.span().0, rhs.span().1);
+            if chained && !self.sess.has_fatal() {
+                self.sess
+                    .new_error()
+                    .warning("comparison operators cannot be chained")
+                    .span(span.0, span.1)
+                    .label_help("use parentheses to compare the result, or '&&' to join conditions")
+                    .emit();
+            }
+            lhs = Expr::Binary { op, lhs: Box::new(lhs), rhs: Box::new(rhs), span };
+        }
+        Ok(lhs)
+    }
+
+    fn parse_type_check(&mut self, expr: Expr) -> PResult<Expr> {
+        if self.check_ident("is") {
+            return Err(self.fo4_only("type checks"));
+        }
+        let is_token = self.bump();
+        self.expect_operand(&is_token)?;
+        let ty = self.parse_type()?;
+        let span = (expr.span().0, self.prev_hi);
+        Ok(Expr::TypeCheck { expr: Box::new(expr), ty, span })
+    }
+
+    fn parse_unary(&mut self) -> PResult<Expr> {
+        let op = match self.token.kind {
+            TokenKind::Minus => UnOp::Neg,
+            TokenKind::Not => UnOp::Not,
+            _ => return self.parse_cast(),
+        };
+        let op_token = self.bump();
+        self.expect_operand(&op_token)?;
+        self.negated = op == UnOp::Neg
+            && self.token.kind == TokenKind::Literal(LitKind::Integer(i32::MIN, false));
+        let lo = op_token.lo();
+        let expr = self.parse_unary()?;
+        let span = (lo, expr.span().1);
+        Ok(Expr::Unary { op, expr: Box::new(expr), span })
+    }
+
+    fn parse_cast(&mut self) -> PResult<Expr> {
+        let mut expr = self.parse_postfix()?;
+        while !self.at_eol() && self.eat_keyword(KwKind::As) {
+            let ty = self.parse_type()?;
+            let span = (expr.span().0, self.prev_hi);
+            expr = Expr::Cast { expr: Box::new(expr), ty, span };
+        }
+        Ok(expr)
+    }
+
+    fn parse_postfix(&mut self) -> PResult<Expr> {
+        let mut expr = self.parse_primary()?;
+        let lo = expr.span().0;
+        while !self.at_eol() {
+            expr = match self.token.kind {
+                TokenKind::Dot => {
+                    self.bump();
+                    let field = self.expect_ident()?;
+                    Expr::Member { base: Box::new(expr), field, span: (lo, self.prev_hi) }
+                }
+                TokenKind::LSquare => {
+                    self.bump();
+                    let index = self.parse_expr()?;
+                    self.expect(TokenKind::RSquare, "expected ']' to close index")?;
+                    let span = (lo, self.prev_hi);
+                    Expr::Index { base: Box::new(expr), index: Box::new(index), span }
+                }
+                TokenKind::LParen => {
+                    let args = self.parse_args()?;
+                    Expr::Call { callee: Box::new(expr), args, span: (lo, self.prev_hi) }
+                }
+                _ => break,
+            };
+        }
+        Ok(expr)
+    }
+
+    fn parse_args(&mut self) -> PResult<Vec<Arg>> {
+        let opener = self.expect(TokenKind::LParen, "expected '('")?.span();
+        let mut args = vec![];
+        loop {
+            if self.at_eol() {
+                self.error(
+                    "expected ')' to close argument list",
+                    opener.0,
+                    opener.1,
+                    "argument list is never closed",
+                );
+                return Err(ParseError);
+            }
+            if self.check(TokenKind::RParen) {
+                break;
+            }
+            args.push(self.parse_arg()?);
+            if !self.at_eol() && !self.check(TokenKind::RParen) {
+                let comma = self.expect(TokenKind::Comma, "expected ',' or ')' after argument")?;
+                if self.check(TokenKind::RParen) {
+                    self.error(
+                        "trailing comma in argument list",
+                        comma.lo(),
+                        comma.hi(),
+                        "remove this",
+                    );
+                }
+            }
+        }
+        self.bump();
+        Ok(args)
+    }
+
+    fn parse_arg(&mut self) -> PResult<Arg> {
+        let lo = self.token.lo();
+        let named = matches!(self.token.kind, TokenKind::Ident(_))
+            && *self.look_ahead(1) == TokenKind::Equal;
+        let name = if named {
+            if self.sess.game != Game::FO4 {
+                self.fo4_only("named arguments");
+            }
+            let name = self.expect_ident()?;
+            self.bump();
+            Some(name)
+        } else {
+            None
+        };
+        let value = self.parse_expr()?;
+        Ok(Arg { name, value, span: (lo, self.prev_hi) })
+    }
+
+    fn parse_primary(&mut self) -> PResult<Expr> {
+        match self.token.kind {
+            TokenKind::Literal(_) => self.parse_literal(),
+            TokenKind::Ident(_) => {
+                let lo = self.token.lo();
+                let name = self.expect_ident()?;
+                Ok(Expr::Ident { name, span: (lo, self.prev_hi) })
+            }
+            TokenKind::LParen => {
+                let lo = self.bump().lo();
+                let expr = self.parse_expr()?;
+                self.expect(TokenKind::RParen, "expected ')' to close parenthesis")?;
+                Ok(Expr::Paren { expr: Box::new(expr), span: (lo, self.prev_hi) })
+            }
+            TokenKind::Keyword(KwKind::New) => self.parse_new(),
+            TokenKind::Keyword(KwKind::_Self) => Ok(Expr::SelfRef { span: self.bump().span() }),
+            TokenKind::Keyword(KwKind::Parent) => Ok(Expr::ParentRef { span: self.bump().span() }),
+            _ => Err(self.unexpected("expected expression")),
+        }
+    }
+
+    fn parse_new(&mut self) -> PResult<Expr> {
+        let lo = self.expect_keyword(KwKind::New, "expected 'New'")?.lo();
+        let ty = self.parse_base_type()?;
+        if self.at_eol() || !self.check(TokenKind::LSquare) {
+            return match ty {
+                Type::Object(name) if self.sess.game == Game::FO4 => {
+                    Ok(Expr::NewStruct { name, span: (lo, self.prev_hi) })
+                }
+                _ => Err(self.unexpected("expected '[' after array element type")),
+            };
+        }
+        self.bump();
+        if self.check(TokenKind::RSquare) {
+            return Err(self.unexpected("expected array size"));
+        }
+        let size = self.parse_expr()?;
+        self.expect(TokenKind::RSquare, "expected ']' to close array size")?;
+        Ok(Expr::NewArray { ty, size: Box::new(size), span: (lo, self.prev_hi) })
+    }
+
+    fn parse_params(&mut self) -> PResult<Vec<Param>> {
+        self.expect(TokenKind::LParen, "expected '(' to start parameter list")?;
+        let mut params = vec![];
+        if !self.check(TokenKind::RParen) {
+            loop {
+                let lo = self.token.lo();
+                let ty = self.parse_type()?;
+                let name = self.expect_ident()?;
+                let default =
+                    if self.eat(TokenKind::Equal) { Some(self.parse_expr()?) } else { None };
+                params.push(Param { ty, name, default, span: (lo, self.prev_hi) });
+                if !self.eat(TokenKind::Comma) {
+                    break;
+                }
+            }
+        }
+        self.expect(TokenKind::RParen, "expected ')' to close parameter list")?;
+        Ok(params)
+    }
+
+    pub fn parse_function(&mut self) -> PResult<Function> {
+        let lo = self.token.lo();
+        let return_type =
+            if self.check_keyword(KwKind::Function) { None } else { Some(self.parse_type()?) };
+        let fn_token = self.expect_keyword(KwKind::Function, "expected 'Function'")?;
+        let name = self.expect_ident()?;
+        let params = self.parse_params()?;
+        let mut flags = vec![];
+        while !self.at_eol() {
+            let flag = match self.token.kind {
+                TokenKind::Keyword(KwKind::BetaOnly) => FnFlag::BetaOnly,
+                TokenKind::Keyword(KwKind::DebugOnly) => FnFlag::DebugOnly,
+                TokenKind::Keyword(KwKind::Global) => FnFlag::Global,
+                TokenKind::Keyword(KwKind::Native) => FnFlag::Native,
+                TokenKind::Keyword(KwKind::Const) => {
+                    self.misplaced_flag("function", "variables and properties");
+                    continue;
+                }
+                _ if self.check_ident("betaonly") || self.check_ident("debugonly") => {
+                    return Err(self.fo4_only("beta-only and debug-only functions"));
+                }
+                _ => return Err(self.unexpected("expected function flag or end of line")),
+            };
+            self.bump();
+            flags.push(flag);
+        }
+        let doc = self.take_doc();
+        if flags.contains(&FnFlag::Native) {
+            // native functions have no body and no closing keyword
+            return Ok(Function {
+                return_type,
+                name,
+                params,
+                flags,
+                body: vec![],
+                doc,
+                span: (lo, self.prev_hi),
+            });
+        }
+        let body = self.parse_block(&[KwKind::EndFunction], fn_token.span(), "EndFunction")?;
+        self.expect_keyword(KwKind::EndFunction, "expected 'EndFunction'")?;
+        let span = (lo, self.prev_hi);
+        self.expect_eol()?;
+        Ok(Function { return_type, name, params, flags, body, doc, span })
+    }
+
+    pub fn parse_property(&mut self) -> PResult<Property> {
+        let lo = self.token.lo();
+        let ty = self.parse_type()?;
+        let property_token = self.expect_keyword(KwKind::Property, "expected 'Property'")?;
+        let name_token = self.token.span();
+        let name = self.expect_ident()?;
+        let init = if self.eat(TokenKind::Equal) { Some(self.parse_literal()?) } else { None };
+        let mut kind = PropertyKind::Full;
+        let mut flags = vec![];
+        while !self.at_eol() {
+            match self.token.kind {
+                TokenKind::Keyword(KwKind::Auto) => kind = PropertyKind::Auto,
+                TokenKind::Keyword(KwKind::AutoReadOnly) => kind = PropertyKind::AutoReadOnly,
+                TokenKind::Keyword(KwKind::Const) => flags.push(PropertyFlag::Const),
+                TokenKind::Keyword(KwKind::BetaOnly) | TokenKind::Keyword(KwKind::DebugOnly) => {
+                    self.misplaced_flag("property", "functions");
+                    continue;
+                }
+                _ if self.check_ident("conditional") => flags.push(PropertyFlag::Conditional),
+                _ if self.check_ident("hidden") => flags.push(PropertyFlag::Hidden),
+                _ if self.check_ident("mandatory") && self.sess.game == Game::FO4 => {
+                    flags.push(PropertyFlag::Mandatory)
+                }
+                _ if self.check_ident("const") => return Err(self.fo4_only("const properties")),
+                _ if self.check_ident("mandatory") => {
+                    return Err(self.fo4_only("mandatory properties"))
+                }
+                _ => return Err(self.unexpected("expected property flag or end of line")),
+            }
+            self.bump();
+        }
+        let doc = self.take_doc();
+        if kind == PropertyKind::AutoReadOnly && init.is_none() {
+            self.error(
+                "auto read-only property without initial value",
+                name_token.0,
+                name_token.1,
+                "add a value with '= <value>'",
+            );
+        }
+        if kind != PropertyKind::Full {
+            return Ok(Property {
+                ty,
+                name,
+                kind,
+                flags,
+                init,
+                getter: None,
+                setter: None,
+                doc,
+                span: (lo, self.prev_hi),
+            });
+        }
+        let mut getter = None;
+        let mut setter = None;
+        while !self.check_keyword(KwKind::EndProperty) {
+            if self.check(TokenKind::Eof) {
+                return Err(self.unterminated(property_token.span(), "EndProperty"));
+            }
+            let fn_lo = self.token.lo();
+            let function = match self.recover(Self::parse_function) {
+                Some(function) => function,
+                None => continue,
+            };
+            let slot = if function.name.eq_ignore_ascii_case("get") {
+                &mut getter
+            } else if function.name.eq_ignore_ascii_case("set") {
+                &mut setter
+            } else {
+                self.error(
+                    "only 'Get' and 'Set' functions are allowed in a property",
+                    fn_lo,
+                    function.span.1,
+                    "invalid property function",
+                );
+                continue;
+            };
+            if slot.is_some() {
+                self.error(
+                    "property function defined more than once",
+                    fn_lo,
+                    function.span.1,
+                    "duplicate definition",
+                );
+            }
+            *slot = Some(Box::new(function));
+        }
+        self.bump();
+        let span = (lo, self.prev_hi);
+        self.expect_eol()?;
+        Ok(Property { ty, name, kind, flags, init, getter, setter, doc, span })
+    }
+
+    pub fn parse_event(&mut self) -> PResult<Event> {
+        let event_token = self.expect_keyword(KwKind::Event, "expected 'Event'")?;
+        let mut name = self.expect_ident()?;
+        // remote events are qualified with the sending script, e.g. `Actor.OnDeath`
+        if self.sess.game == Game::FO4 && self.eat(TokenKind::Dot) {
+            name.push('.');
+            name.push_str(&self.expect_ident()?);
+        }
+        let params = self.parse_params()?;
+        let is_native = !self.at_eol() && self.eat_keyword(KwKind::Native);
+        if !self.at_eol() {
+            return Err(self.unexpected("expected 'Native' or end of line"));
+        }
+        let doc = self.take_doc();
+        if is_native {
+            let span = (event_token.lo(), self.prev_hi);
+            return Ok(Event { name, params, is_native, body: vec![], doc, span });
+        }
+        let body = self.parse_block(&[KwKind::EndEvent], event_token.span(), "EndEvent")?;
+        self.bump();
+        let span = (event_token.lo(), self.prev_hi);
+        self.expect_eol()?;
+        Ok(Event { name, params, is_native, body, doc, span })
+    }
+
+    pub fn parse_struct(&mut self) -> PResult<Struct> {
+        if self.check_ident("struct") {
+            let err = self.fo4_only("structs");
+            self.skip_unsupported_block("endstruct");
+            return Err(err);
+        }
+        let struct_token = self.expect_keyword(KwKind::Struct, "expected 'Struct'")?;
+        let name = self.expect_ident()?;
+        self.expect_eol()?;
+        let mut fields = vec![];
+        while !self.check_keyword(KwKind::EndStruct) {
+            if self.check(TokenKind::Eof) {
+                return Err(self.unterminated(struct_token.span(), "EndStruct"));
+            }
+            fields.extend(self.recover(Self::parse_var_decl));
+        }
+        self.bump();
+        let span = (struct_token.lo(), self.prev_hi);
+        self.expect_eol()?;
+        Ok(Struct { name, fields, span })
+    }
+
+    pub fn parse_state(&mut self) -> PResult<State> {
+        let lo = self.token.lo();
+        let is_auto = self.eat_keyword(KwKind::Auto);
+        let state_token = self.expect_keyword(KwKind::State, "expected 'State'")?;
+        let name = self.expect_ident()?;
+        self.expect_eol()?;
+        let mut members = vec![];
+        while !self.check_keyword(KwKind::EndState) {
+            if self.check(TokenKind::Eof) {
+                return Err(self.unterminated(state_token.span(), "EndState"));
+            }
+            let member = if self.check_keyword(KwKind::Event) {
+                self.recover(Self::parse_event).map(StateMember::Event)
+            } else {
+                self.recover(Self::parse_function).map(StateMember::Function)
+            };
+            members.extend(member);
+        }
+        self.bump();
+        let span = (lo, self.prev_hi);
+        self.expect_eol()?;
+        Ok(State { name, is_auto, members, span })
+    }
+
+    pub fn parse_import(&mut self) -> PResult<Import> {
+        let lo = self.expect_keyword(KwKind::Import, "expected 'Import'")?.lo();
+        let name = self.expect_ident()?;
+        let span = (lo, self.prev_hi);
+        self.expect_eol()?;
+        Ok(Import { name, span })
+    }
+
+    pub fn parse_custom_event(&mut self) -> PResult<CustomEvent> {
+        if self.check_ident("customevent") {
+            return Err(self.fo4_only("custom events"));
+        }
+        let lo = self.expect_keyword(KwKind::CustomEvent, "expected 'CustomEvent'")?.lo();
+        let name = self.expect_ident()?;
+        let span = (lo, self.prev_hi);
+        self.expect_eol()?;
+        Ok(CustomEvent { name, span })
+    }
+
+    pub fn parse_group(&mut self) -> PResult<Group> {
+        if self.check_ident("group") {
+            let err = self.fo4_only("property groups");
+            self.skip_unsupported_block("endgroup");
+            return Err(err);
+        }
+        let group_token = self.expect_keyword(KwKind::Group, "expected 'Group'")?;
+        let name = self.expect_ident()?;
+        let mut flags = vec![];
+        while !self.at_eol() {
+            flags.push(self.expect_ident()?);
+        }
+        let mut properties = vec![];
+        while !self.check_keyword(KwKind::EndGroup) {
+            if self.check(TokenKind::Eof) {
+                return Err(self.unterminated(group_token.span(), "EndGroup"));
+            }
+            properties.extend(self.recover(Self::parse_property));
+        }
+        self.bump();
+        let span = (group_token.lo(), self.prev_hi);
+        self.expect_eol()?;
+        Ok(Group { name, flags, properties, span })
+    }
+
+    /// Parses a whole script, the header followed by every member until the end of the file.
+    ///
+    /// A member that fails to parse is skipped up to the next line so the remaining members are
+    /// still parsed and every error is reported. Only a broken header fails the whole script.
+    ///
+    /// ```
+    /// use libpapyrus::{Game, Parser, ParserSession};
+    ///
+    /// let sess = ParserSession::from_string("ScriptName Foo\nInt x = 5", Game::TESV);
+    /// let script = Parser::from_sess(&sess).parse_script().unwrap();
+    /// assert_eq!(script.header.name, "Foo");
+    /// assert_eq!(script.members.len(), 1);
+    /// ```
+    pub fn parse_script(&mut self) -> PResult<Script> {
+        let header = self.parse_header()?;
+        let mut members = vec![];
+        while !self.check(TokenKind::Eof) {
+            members.extend(self.recover(Self::parse_member));
+        }
+        if let Some(doc) = self.doc.take() {
+            self.unattached_doc(&doc);
+        }
+        self.check_duplicates(&members);
+        let span = (header.span.0, self.prev_hi);
+        Ok(Script { header, members, span })
+    }
+
+    // Papyrus names are case-insensitive and functions cannot be overloaded, so any two members
+    // sharing a name within a namespace clash
+    fn check_duplicates(&self, members: &[Member]) {
+        let mut names = vec![];
+        for member in members {
+            match member {
+                Member::Property(property) => names.push(("value", &property.name, property.span)),
+                Member::Group(group) => names.extend(
+                    group
+                        .properties
+                        .iter()
+                        .map(|property| ("value", &property.name, property.span)),
+                ),
+                Member::Var(decl) => names.push(("value", &decl.name, decl.span)),
+                Member::Function(function) => {
+                    names.push(("function", &function.name, function.span))
+                }
+                Member::Event(event) => names.push(("function", &event.name, event.span)),
+                Member::State(state) => names.push(("state", &state.name, state.span)),
+                Member::Struct(item) => names.push(("struct", &item.name, item.span)),
+                Member::CustomEvent(event) => names.push(("custom event", &event.name, event.span)),
+                Member::Import(_) => (),
+            }
+        }
+        let mut seen = HashMap::new();
+        for (namespace, name, span) in names {
+            let key = (namespace, name.to_ascii_lowercase());
+            let first = match seen.get(&key) {
+                Some(first) => *first,
+                None => {
+                    seen.insert(key, span);
+                    continue;
+                }
+            };
+            if self.sess.has_fatal() {
+                return;
+            }
+            // only the first line, declarations with a body span several
+            let (lo, hi) = self.first_line(span);
+            let (first_lo, first_hi) = self.first_line(first);
+            self.sess
+                .new_error()
+                .error(&format!("'{}' is declared more than once", name))
+                .span(lo, hi)
+                .label_error("redeclared here")
+                .label_at(first_lo, first_hi, AnnotationType::Info, "first declared here")
+                .emit();
+        }
+    }
+
+    fn first_line(&self, (lo, hi): (usize, usize)) -> (usize, usize) {
+        match self.sess.src.content[lo..hi].find(['\r', '\n']) {
+            Some(len) => (lo, lo + len),
+            None => (lo, hi),
+        }
+    }
+
+    // the compiler requires the file to be named after the script, namespaces map to directories
+    fn check_script_name(&self, name: &str, (lo, hi): (usize, usize)) {
+        let filename = &self.sess.src.filename;
+        if filename == "<stdin>" {
+            return;
+        }
+        let stem = Path::new(filename).file_stem().unwrap_or_default().to_string_lossy();
+        let base_name = split_namespace(name).pop().unwrap_or_default();
+        if !base_name.eq_ignore_ascii_case(&stem) {
+            self.error(
+                "script name does not match the file name",
+                lo,
+                hi,
+                &format!("expected '{}'", stem),
+            );
+        }
+    }
+
+    fn skip_line(&mut self) {
+        self.bump();
+        while !self.at_eol() {
+            self.bump();
+        }
+    }
+
+    // skips a block this game does not support up to its `closer`, which lexes as an identifier, so
+    // its body is not parsed as script members
+    fn skip_unsupported_block(&mut self, closer: &str) {
+        self.skip_line();
+        while !self.check(TokenKind::Eof) && !self.check_ident(closer) {
+            self.skip_line();
+        }
+        if self.check_ident(closer) {
+            self.bump();
+        }
+    }
+
+    // runs `parse` on the statement or declaration at the current token, synchronizing after it
+    // if it fails so the caller can carry on with the next one
+    fn recover<T>(&mut self, parse: impl FnOnce(&mut Self) -> PResult<T>) -> Option<T> {
+        let lo = self.token.lo();
+        let block = self.block_on_line();
+        match parse(self) {
+            Ok(node) => Some(node),
+            Err(ParseError) => {
+                self.synchronize(lo, block);
+                None
+            }
+        }
+    }
+
+    /// Skips what is left of the statement or declaration starting at `lo` after a parse error.
+    ///
+    /// Parsing resumes on the next line. If the error is on the first line of a `block`, given as
+    /// its opening and closing keywords, the whole block is skipped since its body would otherwise
+    /// be parsed as something it is not. A property is only skipped up to `EndProperty` if a `Get`
+    /// or `Set` function follows, since it may have been meant as an auto property.
+    pub fn synchronize(&mut self, lo: usize, block: Option<(KwKind, KwKind)>) {
+        let location = |offset| self.sess.location(offset).0;
+        let on_first_line = self.prev_hi <= lo || location(self.prev_hi) == location(lo);
+        // the failed parse may not have consumed anything
+        if self.token.lo() == lo {
+            self.bump();
+        }
+        while !self.at_eol() {
+            self.bump();
+        }
+        if let (true, Some((opener, closer))) = (on_first_line, block) {
+            // a property missing its flags may have been meant as an auto property
+            if closer == KwKind::EndProperty && !self.at_property_function() {
+                return;
+            }
+            let mut depth = 1;
+            while depth > 0 && !self.check(TokenKind::Eof) {
+                if self.check_keyword(opener.clone()) {
+                    depth += 1;
+                } else if self.check_keyword(closer.clone()) {
+                    depth -= 1;
+                }
+                self.skip_line();
+            }
+        }
+    }
+
+    // whether the current line starts the `Get` or `Set` function of a property
+    fn at_property_function(&mut self) -> bool {
+        // the return type goes first, which may be an array
+        for n in 0..4 {
+            let kind = if n == 0 { &self.token.kind } else { self.look_ahead(n) };
+            if *kind == TokenKind::Keyword(KwKind::Function) {
+                return matches!(self.look_ahead(n + 1), TokenKind::Ident(name)
+                    if name.eq_ignore_ascii_case("get") || name.eq_ignore_ascii_case("set"));
+            }
+        }
+        false
+    }
+
+    // the opening and closing keywords of the block started on the current line, if any
+    fn block_on_line(&mut self) -> Option<(KwKind, KwKind)> {
+        let mut block = None;
+        let mut n = 0;
+        loop {
+            let kind = if n == 0 { &self.token.kind } else { self.look_ahead(n) };
+            match kind {
+                TokenKind::Eof => return block,
+                // native functions and events have no body, nor do auto properties
+                TokenKind::Keyword(KwKind::Native) => return None,
+                TokenKind::Keyword(KwKind::Auto) | TokenKind::Keyword(KwKind::AutoReadOnly)
+                    if matches!(block, Some((KwKind::Property, _))) =>
+                {
+                    return None
+                }
+                TokenKind::Keyword(kw) if block.is_none() => {
+                    block = block_closer(kw).map(|closer| (kw.clone(), closer));
+                }
+                _ => (),
+            }
+            n += 1;
+        }
+    }
+
+    fn parse_member(&mut self) -> PResult<Member> {
+        // `Struct Name` and `Group Name Flags...` lines in games where these are identifiers, as
+        // opposed to declarations with a type named like them
+        let is_struct = self.check_ident("struct") && *self.look_ahead(2) == TokenKind::Eof;
+        let is_group = self.check_ident("group")
+            && matches!(self.look_ahead(2), TokenKind::Eof | TokenKind::Ident(_));
+        let member = match self.token.kind {
+            TokenKind::Keyword(KwKind::Import) => Member::Import(self.parse_import()?),
+            TokenKind::Keyword(KwKind::CustomEvent) => {
+                Member::CustomEvent(self.parse_custom_event()?)
+            }
+            TokenKind::Ident(_) if self.check_ident("customevent") => {
+                Member::CustomEvent(self.parse_custom_event()?)
+            }
+            TokenKind::Keyword(KwKind::Auto) | TokenKind::Keyword(KwKind::State) => {
+                Member::State(self.parse_state()?)
+            }
+            TokenKind::Keyword(KwKind::Event) => Member::Event(self.parse_event()?),
+            TokenKind::Keyword(KwKind::Function) => Member::Function(self.parse_function()?),
+            TokenKind::Keyword(KwKind::Struct) => Member::Struct(self.parse_struct()?),
+            TokenKind::Ident(_) if is_struct => Member::Struct(self.parse_struct()?),
+            TokenKind::Keyword(KwKind::Group) => Member::Group(self.parse_group()?),
+            TokenKind::Ident(_) if is_group => Member::Group(self.parse_group()?),
+            _ => {
+                // properties, functions and variables all start with a type
+                let after_type = if *self.look_ahead(1) == TokenKind::LSquare { 3 } else { 1 };
+                let keyword = self.look_ahead(after_type);
+                if *keyword == TokenKind::Keyword(KwKind::Property) {
+                    Member::Property(self.parse_property()?)
+                } else if *keyword == TokenKind::Keyword(KwKind::Function) {
+                    Member::Function(self.parse_function()?)
+                } else if self.is_var_decl_start() {
+                    Member::Var(self.parse_var_decl()?)
+                } else {
+                    return Err(self.unexpected("expected script member"));
+                }
+            }
+        };
+        Ok(member)
+    }
+
+    pub fn parse_header(&mut self) -> PResult<ScriptHeader> {
+        let lo = self
+            .expect_keyword(KwKind::ScriptName, "expected 'ScriptName' at start of script")?
+            .lo();
+        let name_span = self.token.span();
+        let name = self.expect_ident()?;
+        self.check_script_name(&name, name_span);
+        let extends =
+            if self.eat_keyword(KwKind::Extends) { Some(self.expect_ident()?) } else { None };
+        let mut flags = vec![];
+        loop {
+            if self.at_eol() {
+                break;
+            }
+            let flag = match &self.token.kind {
+                TokenKind::Keyword(KwKind::Native) => ScriptFlag::Native,
+                TokenKind::Keyword(KwKind::Const) => ScriptFlag::Const,
+                TokenKind::Keyword(KwKind::BetaOnly) => ScriptFlag::BetaOnly,
+                TokenKind::Keyword(KwKind::DebugOnly) => ScriptFlag::DebugOnly,
+                TokenKind::Ident(ident) if ident.eq_ignore_ascii_case("conditional") => {
+                    ScriptFlag::Conditional
+                }
+                TokenKind::Ident(ident) if ident.eq_ignore_ascii_case("hidden") => {
+                    ScriptFlag::Hidden
+                }
+                TokenKind::Ident(ident)
+                    if ident.eq_ignore_ascii_case("default") && self.sess.game == Game::FO4 =>
+                {
+                    ScriptFlag::Default
+                }
+                // the flag is dropped so the rest of the script is still parsed
+                _ if self.check_ident("const") => {
+                    self.fo4_only("const scripts");
+                    self.bump();
+                    continue;
+                }
+                _ if self.check_ident("betaonly") || self.check_ident("debugonly") => {
+                    self.fo4_only("beta-only and debug-only scripts");
+                    self.bump();
+                    continue;
+                }
+                _ if self.check_ident("default") => {
+                    self.fo4_only("default scripts");
+                    self.bump();
+                    continue;
+                }
+                _ => break,
+            };
+            self.bump();
+            flags.push(flag);
+        }
+        let span = (lo, self.prev_hi);
+        self.expect_eol()?;
+        let doc = self.take_doc();
+        Ok(ScriptHeader { name, extends, flags, doc, span })
+    }
+}
+
+// the keyword closing a block of statements opened by `opener`
+//
+// blocks of declarations, like states, are not skipped on errors since their content still parses
+// as declarations
+fn block_closer(opener: &KwKind) -> Option<KwKind> {
+    let closer = match opener {
+        KwKind::If => KwKind::EndIf,
+        KwKind::While => KwKind::EndWhile,
+        KwKind::Function => KwKind::EndFunction,
+        KwKind::Event => KwKind::EndEvent,
+        KwKind::Property => KwKind::EndProperty,
+        KwKind::State => KwKind::EndState,
+        _ => return None,
+    };
+    Some(closer)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::*;
+
+    #[test]
+    fn header() {
+        let sess = ParserSession::from_string("ScriptName Foo", Game::TESV);
+        let header = Parser::from_sess(&sess).parse_header().unwrap();
+        assert_eq!(
+            ScriptHeader {
+                name: "Foo".to_string(),
+                extends: None,
+                flags: vec![],
+                doc: None,
+                span: (0, 14),
+            },
+            header
+        );
+    }
+
+    #[test]
+    fn header_extends() {
+        let sess = ParserSession::from_string("scriptname Foo extends Bar", Game::TESV);
+        let header = Parser::from_sess(&sess).parse_header().unwrap();
+        assert_eq!(
+            ScriptHeader {
+                name: "Foo".to_string(),
+                extends: Some("Bar".to_string()),
+                flags: vec![],
+                doc: None,
+                span: (0, 26),
+            },
+            header
+        );
+    }
+
+    #[test]
+    fn header_newline() {
+        let sess = ParserSession::from_string("ScriptName Foo\nHidden", Game::TESV);
+        let mut parser = Parser::from_sess(&sess);
+        assert_eq!(Ok(vec![]), parser.parse_header().map(|header| header.flags));
+        assert_eq!(TokenKind::Ident("Hidden".to_string()), parser.token.kind);
+    }
+
+    #[test]
+    fn script() {
+        let script = "ScriptName Foo Extends Bar\n\
+                      \n\
+                      ; the count\n\
+                      Int Property Count Auto\n\
+                      \n\
+                      Int Function Next()\n\
+                      \tCount += 1\n\
+                      \tReturn Count\n\
+                      EndFunction\n";
+        let sess = ParserSession::from_string(script, Game::TESV);
+        let script = Parser::from_sess(&sess).parse_script().unwrap();
+        assert_eq!("Foo", script.header.name);
+        match &script.members[..] {
+            [Member::Property(property), Member::Function(function)] => {
+                assert_eq!((40, 63), property.span);
+                assert_eq!("Next", function.name);
+                assert_eq!(2, function.body.len());
+                assert_eq!((65, 122), function.span);
+            }
+            members => panic!("expected a property and a function, found {:?}", members),
+        }
+        assert_eq!((0, 122), script.span);
+        assert!(sess.diagnostics().is_empty());
+    }
+
+    #[test]
+    fn script_docs() {
+        let script = "ScriptName Foo\n\
+                      { The script }\n\
+                      Int Property Count Auto\n\
+                      { The count }\n\
+                      \n\
+                      Int Function Next()\n\
+                      { Adds one }\n\
+                      \tReturn 1\n\
+                      EndFunction\n\
+                      Event OnInit() Native\n\
+                      { Starts }\n";
+        let sess = ParserSession::from_string(script, Game::TESV);
+        let script = Parser::from_sess(&sess).parse_script().unwrap();
+        assert!(sess.diagnostics().is_empty());
+        assert_eq!(Some(" The script ".to_string()), script.header.doc);
+        match &script.members[..] {
+            [Member::Property(property), Member::Function(function), Member::Event(event)] => {
+                assert_eq!(Some(" The count ".to_string()), property.doc);
+                assert_eq!(Some(" Adds one ".to_string()), function.doc);
+                assert_eq!(Some(" Starts ".to_string()), event.doc);
+            }
+            members => panic!("expected a property, function and event, found {:?}", members),
+        }
+    }
+
+    #[test]
+    fn misplaced_doc() {
+        let script = "{ Foo }\n\
+                      ScriptName Foo\n\
+                      Function Bar()\n\
+                      \tx = { value } 5\n\
+                      EndFunction\n\
+                      { Baz }\n\
+                      Event Baz()\n\
+                      EndEvent\n\
+                      { orphaned }\n";
+        let sess = ParserSession::from_string(script, Game::TESV);
+        let script = Parser::from_sess(&sess).parse_script().unwrap();
+        assert_eq!(None, script.header.doc);
+        let diagnostics = sess.diagnostics();
+        assert!(diagnostics
+            .iter()
+            .all(|d| d.title == "documentation block is not attached to anything"));
+        let spans = diagnostics.iter().map(|d| d.span).collect::<Vec<_>>();
+        assert_eq!(vec![(0, 7), (43, 52), (67, 74), (96, 108)], spans);
+        match &script.members[..] {
+            [Member::Function(function), Member::Event(event)] => {
+                assert_eq!(None, function.doc);
+                assert_eq!(None, event.doc);
+            }
+            members => panic!("expected a function and an event, found {:?}", members),
+        }
+    }
+
+    #[test]
+    fn script_recovers() {
+        let script = "ScriptName Foo\n\
+                      Int Property = 5\n\
+                      5\n\
+                      Float x\n";
+        let sess = ParserSession::from_string(script, Game::TESV);
+        let script = Parser::from_sess(&sess).parse_script().unwrap();
+        assert!(matches!(script.members[..], [Member::Var(_)]));
+        assert_eq!(2, sess.diagnostics().len());
+    }
+
+    #[test]
+    fn function_recovers() {
+        let script = "ScriptName Foo\n\
+                      Function Bar()\n\
+                      \tInt x = 1\n\
+                      \tx = * 2\n\
+                      \tx += 3\n\
+                      \tReturn\n\
+                      EndFunction\n\
+                      Event OnInit()\n\
+                      EndEvent\n";
+        let sess = ParserSession::from_string(script, Game::TESV);
+        let script = Parser::from_sess(&sess).parse_script().unwrap();
+        let diagnostics = sess.diagnostics();
+        assert_eq!(1, diagnostics.len());
+        assert_eq!((46, 47), diagnostics[0].span);
+        match &script.members[..] {
+            [Member::Function(function), Member::Event(_)] => {
+                assert!(matches!(
+                    function.body[..],
+                    [Stmt::Var(_), Stmt::Assign { .. }, Stmt::Return { .. }]
+                ));
+            }
+            members => panic!("expected a function and an event, found {:?}", members),
+        }
+    }
+
+    #[test]
+    fn function_header_recovers() {
+        let script = "ScriptName Foo\n\
+                      Function Bar(Int)\n\
+                      \tIf x\n\
+                      \t\tReturn\n\
+                      \tEndIf\n\
+                      EndFunction\n\
+                      Function Baz(Int) Native\n\
+                      Float y\n";
+        let sess = ParserSession::from_string(script, Game::TESV);
+        let script = Parser::from_sess(&sess).parse_script().unwrap();
+        let spans = sess.diagnostics().iter().map(|d| d.span).collect::<Vec<_>>();
+        assert_eq!(vec![(31, 32), (83, 84)], spans);
+        assert!(matches!(script.members[..], [Member::Var(_)]));
+    }
+
+    #[test]
+    fn property_header_recovers() {
+        let script = "ScriptName Foo\n\
+                      Int Property Foo Bogus\n\
+                      \tInt Function Get()\n\
+                      \t\tReturn 1\n\
+                      \tEndFunction\n\
+                      EndProperty\n\
+                      Int Property Bar Atuo\n\
+                      Float x\n";
+        let sess = ParserSession::from_string(script, Game::TESV);
+        let script = Parser::from_sess(&sess).parse_script().unwrap();
+        let spans = sess.diagnostics().iter().map(|d| d.span).collect::<Vec<_>>();
+        assert_eq!(vec![(32, 37), (111, 115)], spans);
+        assert!(matches!(script.members[..], [Member::Var(_)]));
+    }
+
+    #[test]
+    fn state_header_recovers() {
+        let script = "ScriptName Foo\n\
+                      Auto State Waiting Bogus\n\
+                      \tEvent OnInit()\n\
+                      \tEndEvent\n\
+                      EndState\n\
+                      Float x\n";
+        let sess = ParserSession::from_string(script, Game::TESV);
+        let script = Parser::from_sess(&sess).parse_script().unwrap();
+        let diagnostics = sess.diagnostics();
+        assert_eq!(1, diagnostics.len());
+        assert_eq!((34, 39), diagnostics[0].span);
+        assert!(matches!(script.members[..], [Member::Var(_)]));
+    }
+
+    #[test]
+    fn script_duplicates() {
+        let script = "ScriptName Foo\n\
+                      Function Bar()\n\
+                      EndFunction\n\
+                      Int Property bar Auto\n\
+                      Function BAR()\n\
+                      EndFunction";
+        let sess = ParserSession::from_string(script, Game::TESV);
+        let script = Parser::from_sess(&sess).parse_script().unwrap();
+        assert_eq!(3, script.members.len());
+        let diagnostics = sess.diagnostics();
+        assert_eq!(1, diagnostics.len());
+        assert_eq!("'BAR' is declared more than once", diagnostics[0].title);
+        assert_eq!((64, 78), diagnostics[0].span);
+        assert_eq!((15, 29), diagnostics[0].labels[0].span);
+    }
+
+    #[test]
+    fn header_file_name() {
+        let path = env::temp_dir().join("libpapyrus_header_file_name.psc");
+        fs::write(&path, "ScriptName LIBPAPYRUS_header_file_name\nScriptName Other").unwrap();
+        let sess = ParserSession::from_file(&path, Game::TESV).unwrap();
+        fs::remove_file(&path).unwrap();
+        let mut parser = Parser::from_sess(&sess);
+        parser.parse_header().unwrap();
+        assert!(sess.diagnostics().is_empty());
+        parser.parse_header().unwrap();
+        let diagnostics = sess.diagnostics();
+        assert_eq!("script name does not match the file name", diagnostics[0].title);
+        assert_eq!((50, 55), diagnostics[0].span);
+        assert_eq!(
+            Some("expected 'libpapyrus_header_file_name'".to_string()),
+            diagnostics[0].label
+        );
+    }
+
+    #[test]
+    fn import() {
+        let sess = ParserSession::from_string(
+            "Import Foo
+import Bar",
+            Game::TESV,
+        );
+        let mut parser = Parser::from_sess(&sess);
+        assert_eq!(Ok(Import { name: "Foo".to_string(), span: (0, 10) }), parser.parse_import());
+        assert_eq!(Ok(Import { name: "Bar".to_string(), span: (11, 21) }), parser.parse_import());
+    }
+
+    #[test]
+    fn import_not_ident() {
+        let sess = ParserSession::from_string("Import 5", Game::TESV);
+        assert_eq!(Err(ParseError), Parser::from_sess(&sess).parse_import());
+        let diagnostics = sess.diagnostics();
+        assert_eq!("expected identifier", diagnostics[0].title);
+        assert_eq!((7, 8), diagnostics[0].span);
+    }
+
+    #[test]
+    fn event() {
+        let script = "Event OnHit(ObjectReference akAggressor)\n\
+                      \tDebug.Trace(akAggressor)\n\
+                      EndEvent";
+        let sess = ParserSession::from_string(script, Game::TESV);
+        let event = Parser::from_sess(&sess).parse_event().unwrap();
+        assert_eq!("OnHit", event.name);
+        assert_eq!(
+            vec![Param {
+                ty: Type::Object("ObjectReference".to_string()),
+                name: "akAggressor".to_string(),
+                default: None,
+                span: (12, 39),
+            }],
+            event.params
+        );
+        assert!(!event.is_native);
+        assert_eq!(1, event.body.len());
+        assert_eq!((0, script.len()), event.span);
+    }
+
+    #[test]
+    fn event_native() {
+        let sess = ParserSession::from_string("Event OnInit() Native\nInt x", Game::TESV);
+        let mut parser = Parser::from_sess(&sess);
+        let event = parser.parse_event().unwrap();
+        assert!(event.is_native);
+        assert_eq!((0, 21), event.span);
+        assert_eq!(TokenKind::Keyword(KwKind::Int), parser.token.kind);
+    }
+
+    #[test]
+    fn event_remote() {
+        let sess =
+            ParserSession::from_string("Event Actor.OnDeath(Actor killer)\nEndEvent", Game::FO4);
+        assert_eq!("Actor.OnDeath", Parser::from_sess(&sess).parse_event().unwrap().name);
+    }
+
+    #[test]
+    fn event_unterminated() {
+        let sess = ParserSession::from_string("Event OnInit()\n", Game::TESV);
+        assert_eq!(Err(ParseError), Parser::from_sess(&sess).parse_event());
+        let diagnostics = sess.diagnostics();
+        assert_eq!("expected 'EndEvent' before end of file", diagnostics[0].title);
+        assert_eq!((0, 5), diagnostics[0].span);
+    }
+
+    #[test]
+    fn struct_fields() {
+        let script = "Struct Point\n\
+                      \tFloat x = 1.5\n\
+                      \tFloat y Const\n\
+                      EndStruct";
+        let sess = ParserSession::from_string(script, Game::FO4);
+        let point = Parser::from_sess(&sess).parse_struct().unwrap();
+        assert_eq!(
+            Struct {
+                name: "Point".to_string(),
+                fields: vec![
+                    VarDecl {
+                        ty: Type::Float,
+                        name: "x".to_string(),
+                        init: Some(Expr::Literal {
+                            lit: LitKind::Float(1.5, "1.5".to_string()),
+                            span: (24, 27)
+                        }),
+                        flags: vec![],
+                        span: (14, 27),
+                    },
+                    VarDecl {
+                        ty: Type::Float,
+                        name: "y".to_string(),
+                        init: None,
+                        flags: vec![VarFlag::Const],
+                        span: (29, 42),
+                    },
+                ],
+                span: (0, script.len()),
+            },
+            point
+        );
+    }
+
+    #[test]
+    fn struct_wrong_game() {
+        let sess = ParserSession::from_string("Struct Point\nEndStruct", Game::TESV);
+        assert_eq!(Err(ParseError), Parser::from_sess(&sess).parse_struct());
+        let diagnostics = sess.diagnostics();
+        assert_eq!("structs are only available in Fallout 4", diagnostics[0].title);
+        assert_eq!((0, 6), diagnostics[0].span);
+
+        let script = "ScriptName Foo\n\
+                      Struct Point\n\
+                      \tInt x\n\
+                      EndStruct\n\
+                      Struct Property Bar Auto\n";
+        let sess = ParserSession::from_string(script, Game::TESV);
+        let script = Parser::from_sess(&sess).parse_script().unwrap();
+        let diagnostics = sess.diagnostics();
+        assert_eq!(1, diagnostics.len());
+        assert_eq!("structs are only available in Fallout 4", diagnostics[0].title);
+        assert_eq!((15, 21), diagnostics[0].span);
+        assert!(matches!(script.members[..], [Member::Property(_)]));
+    }
+
+    #[test]
+    fn custom_event() {
+        let sess = ParserSession::from_string("CustomEvent OnHit\nInt x", Game::FO4);
+        let mut parser = Parser::from_sess(&sess);
+        assert_eq!(
+            Ok(CustomEvent { name: "OnHit".to_string(), span: (0, 17) }),
+            parser.parse_custom_event()
+        );
+        assert_eq!(TokenKind::Keyword(KwKind::Int), parser.token.kind);
+    }
+
+    #[test]
+    fn custom_event_wrong_game() {
+        let sess = ParserSession::from_string("CustomEvent OnHit", Game::TESV);
+        assert_eq!(Err(ParseError), Parser::from_sess(&sess).parse_member());
+        let diagnostics = sess.diagnostics();
+        assert_eq!("custom events are only available in Fallout 4", diagnostics[0].title);
+        assert_eq!((0, 11), diagnostics[0].span);
+    }
+
+    #[test]
+    fn event_name_params() {
+        let sess = ParserSession::from_string(
+            "Function Send(CustomEventName a, ScriptEventName b)\nEndFunction",
+            Game::FO4,
+        );
+        let function = Parser::from_sess(&sess).parse_function().unwrap();
+        let types = function.params.iter().map(|param| &param.ty).collect::<Vec<_>>();
+        assert_eq!(vec![&Type::CustomEventName, &Type::ScriptEventName], types);
+    }
+
+    #[test]
+    fn state() {
+        let sess = ParserSession::from_string("State Foo\nEndState", Game::TESV);
+        assert_eq!(
+            Ok(State { name: "Foo".to_string(), is_auto: false, members: vec![], span: (0, 18) }),
+            Parser::from_sess(&sess).parse_state()
+        );
+    }
+
+    #[test]
+    fn state_auto() {
+        let sess = ParserSession::from_string("Auto State Foo\nEndState", Game::TESV);
+        let state = Parser::from_sess(&sess).parse_state().unwrap();
+        assert!(state.is_auto);
+        assert_eq!((0, 23), state.span);
+    }
+
+    #[test]
+    fn state_function() {
+        let script = "State Foo\n\
+                      \tFunction Bar()\n\
+                      \tEndFunction\n\
+                      \tEvent OnInit()\n\
+                      \tEndEvent\n\
+                      EndState";
+        let sess = ParserSession::from_string(script, Game::TESV);
+        let state = Parser::from_sess(&sess).parse_state().unwrap();
+        match &state.members[..] {
+            [StateMember::Function(function), StateMember::Event(event)] => {
+                assert_eq!("Bar", function.name);
+                assert_eq!((11, 38), function.span);
+                assert_eq!("OnInit", event.name);
+            }
+            _ => panic!("expected a function and an event"),
+        }
+    }
+
+    #[test]
+    fn state_unterminated() {
+        let sess = ParserSession::from_string("Auto State Foo\n", Game::TESV);
+        assert_eq!(Err(ParseError), Parser::from_sess(&sess).parse_state());
+        let diagnostics = sess.diagnostics();
+        assert_eq!("expected 'EndState' before end of file", diagnostics[0].title);
+        assert_eq!((5, 10), diagnostics[0].span);
+    }
+
+    #[test]
+    fn group() {
+        let script = "Group Stats CollapsedOnRef\n\
+                      \tInt Property Health Auto\n\
+                      \tFloat Property Speed = 1.0 AutoReadOnly\n\
+                      EndGroup";
+        let sess = ParserSession::from_string(script, Game::FO4);
+        let group = Parser::from_sess(&sess).parse_group().unwrap();
+        assert_eq!("Stats", group.name);
+        assert_eq!(vec!["CollapsedOnRef".to_string()], group.flags);
+        assert_eq!(2, group.properties.len());
+        assert_eq!("Speed", group.properties[1].name);
+        assert_eq!((0, script.len()), group.span);
+        assert!(sess.diagnostics().is_empty());
+    }
+
+    #[test]
+    fn group_wrong_game() {
+        let sess = ParserSession::from_string("Group Stats\nEndGroup", Game::TESV);
+        assert_eq!(Err(ParseError), Parser::from_sess(&sess).parse_group());
+        assert_eq!("property groups are only available in Fallout 4", sess.diagnostics()[0].title);
+
+        let script = "ScriptName Foo\n\
+                      Group Stats CollapsedOnRef\n\
+                      \tInt Property Health Auto\n\
+                      EndGroup\n\
+                      Int x\n";
+        let sess = ParserSession::from_string(script, Game::TESV);
+        let script = Parser::from_sess(&sess).parse_script().unwrap();
+        let diagnostics = sess.diagnostics();
+        assert_eq!(1, diagnostics.len());
+        assert_eq!("property groups are only available in Fallout 4", diagnostics[0].title);
+        assert_eq!((15, 20), diagnostics[0].span);
+        assert!(matches!(script.members[..], [Member::Var(_)]));
+    }
+
+    #[test]
+    fn property_auto() {
+        let sess = ParserSession::from_string("Int Property Foo Auto Hidden", Game::TESV);
+        let property = Parser::from_sess(&sess).parse_property().unwrap();
+        assert_eq!(
+            Property {
+                ty: Type::Int,
+                name: "Foo".to_string(),
+                kind: PropertyKind::Auto,
+                flags: vec![PropertyFlag::Hidden],
+                init: None,
+                getter: None,
+                setter: None,
+                doc: None,
+                span: (0, 28),
+            },
+            property
+        );
+    }
+
+    #[test]
+    fn property_negative_init() {
+        let sess = ParserSession::from_string("Float Property Foo = -1.50 Auto", Game::TESV);
+        let property = Parser::from_sess(&sess).parse_property().unwrap();
+        assert_eq!(
+            Some(Expr::Literal { lit: LitKind::Float(-1.5, "-1.50".to_string()), span: (21, 26) }),
+            property.init
+        );
+
+        let sess = ParserSession::from_string("String Property Foo = -\"a\" Auto", Game::TESV);
+        assert_eq!(Err(ParseError), Parser::from_sess(&sess).parse_property());
+        assert_eq!("only numbers can be negative", sess.diagnostics()[0].title);
+        assert_eq!((22, 26), sess.diagnostics()[0].span);
+    }
+
+    #[test]
+    fn integer_min() {
+        let script = "ScriptName Foo\n\
+                      Int x = -2147483648\n\
+                      Int y = 2147483648\n\
+                      Function Foo()\n\
+                      \tx = - 2147483648\n\
+                      \tx = 1 + 2147483648\n\
+                      EndFunction\n\
+                      Int z\n";
+        let sess = ParserSession::from_string(script, Game::TESV);
+        let script = Parser::from_sess(&sess).parse_script().unwrap();
+        let spans = sess.diagnostics().iter().map(|d| d.span).collect::<Vec<_>>();
+        assert_eq!(vec![(43, 53), (96, 106)], spans);
+        assert_eq!("could not parse integer literal", sess.diagnostics()[0].title);
+        assert_eq!(3, script.members.len());
+        match &script.members[0] {
+            Member::Var(var) => assert_eq!(
+                Some(Expr::Unary {
+                    op: UnOp::Neg,
+                    expr: Box::new(Expr::Literal {
+                        lit: LitKind::Integer(i32::MIN, false),
+                        span: (24, 34)
+                    }),
+                    span: (23, 34)
+                }),
+                var.init
+            ),
+            member => panic!("expected a variable, found {:?}", member),
+        }
+    }
+
+    #[test]
+    fn property_auto_read_only() {
+        let sess = ParserSession::from_string("Int Property Foo = 5 AutoReadOnly", Game::TESV);
+        let property = Parser::from_sess(&sess).parse_property().unwrap();
+        assert_eq!(PropertyKind::AutoReadOnly, property.kind);
+        assert_eq!(
+            Some(Expr::Literal { lit: LitKind::Integer(5, false), span: (19, 20) }),
+            property.init
+        );
+    }
+
+    #[test]
+    fn property_full() {
+        let script = "Float[] Property Foo\n\
+                      \tFloat[] Function Get()\n\
+                      \tEndFunction\n\
+                      \tFunction Set(Float[] value)\n\
+                      \tEndFunction\n\
+                      EndProperty";
+        let sess = ParserSession::from_string(script, Game::TESV);
+        let property = Parser::from_sess(&sess).parse_property().unwrap();
+        assert_eq!(PropertyKind::Full, property.kind);
+        assert_eq!(Type::Array(Box::new(Type::Float)), property.ty);
+        assert_eq!(
+            Some(Type::Array(Box::new(Type::Float))),
+            property.getter.and_then(|getter| getter.return_type)
+        );
+        let setter = property.setter.unwrap();
+        assert_eq!("Set", setter.name);
+        assert_eq!(1, setter.params.len());
+        assert_eq!((0, script.len()), property.span);
+    }
+
+    #[test]
+    fn function_global() {
+        let sess = ParserSession::from_string("Function Foo() Global\nEndFunction", Game::TESV);
+        let function = Parser::from_sess(&sess).parse_function().unwrap();
+        assert_eq!(
+            Function {
+                return_type: None,
+                name: "Foo".to_string(),
+                params: vec![],
+                flags: vec![FnFlag::Global],
+                body: vec![],
+                doc: None,
+                span: (0, 33),
+            },
+            function
+        );
+    }
+
+    #[test]
+    fn function_debug_only() {
+        let script = "Function Foo() DebugOnly Const\nEndFunction";
+        let sess = ParserSession::from_string(script, Game::FO4);
+        let function = Parser::from_sess(&sess).parse_function().unwrap();
+        assert_eq!(vec![FnFlag::DebugOnly], function.flags);
+        let diagnostics = sess.diagnostics();
+        assert_eq!("'Const' is not a valid function flag", diagnostics[0].title);
+        assert_eq!((25, 30), diagnostics[0].span);
+
+        let sess = ParserSession::from_string(script, Game::TESV);
+        assert_eq!(Err(ParseError), Parser::from_sess(&sess).parse_function());
+        assert_eq!(
+            "beta-only and debug-only functions are only available in Fallout 4",
+            sess.diagnostics()[0].title
+        );
+    }
+
+    #[test]
+    fn function_params() {
+        let sess = ParserSession::from_string(
+            "Int Function Foo(Int foo = 3, Int[] bar, String baz = \"\")\nEndFunction",
+            Game::TESV,
+        );
+        let function = Parser::from_sess(&sess).parse_function().unwrap();
+        assert_eq!(Some(Type::Int), function.return_type);
+        assert_eq!(
+            vec![
+                Param {
+                    ty: Type::Int,
+                    name: "foo".to_string(),
+                    default: Some(Expr::Literal {
+                        lit: LitKind::Integer(3, false),
+                        span: (27, 28)
+                    }),
+                    span: (17, 28),
+                },
+                Param {
+                    ty: Type::Array(Box::new(Type::Int)),
+                    name: "bar".to_string(),
+                    default: None,
+                    span: (30, 39),
+                },
+                Param {
+                    ty: Type::String,
+                    name: "baz".to_string(),
+                    default: Some(Expr::Literal {
+                        lit: LitKind::Str("".to_string()),
+                        span: (54, 56)
+                    }),
+                    span: (41, 56),
+                },
+            ],
+            function.params
+        );
+    }
+
+    #[test]
+    fn function_param_defaults() {
+        let sess = ParserSession::from_string(
+            "Function Foo(Int a = -5, Float b, Int c = 1 + 2)\nEndFunction",
+            Game::TESV,
+        );
+        let function = Parser::from_sess(&sess).parse_function().unwrap();
+        assert!(sess.diagnostics().is_empty());
+        assert_eq!(
+            Some(Expr::Unary {
+                op: UnOp::Neg,
+                expr: Box::new(Expr::Literal { lit: LitKind::Integer(5, false), span: (22, 23) }),
+                span: (21, 23),
+            }),
+            function.params[0].default
+        );
+        assert_eq!(None, function.params[1].default);
+        assert!(matches!(function.params[2].default, Some(Expr::Binary { op: BinOp::Add, .. })));
+    }
+
+    #[test]
+    fn function_native() {
+        let sess = ParserSession::from_string(
+            "Actor Function GetActor(ObjectReference ref) Native Global\nInt x",
+            Game::TESV,
+        );
+        let mut parser = Parser::from_sess(&sess);
+        let function = parser.parse_function().unwrap();
+        assert_eq!(Some(Type::Object("Actor".to_string())), function.return_type);
+        assert_eq!(vec![FnFlag::Native, FnFlag::Global], function.flags);
+        assert_eq!((0, 58), function.span);
+        assert_eq!(TokenKind::Keyword(KwKind::Int), parser.token.kind);
+    }
+
+    fn ident(name: &str, lo: usize) -> Box<Expr> {
+        Box::new(Expr::Ident { name: name.to_string(), span: (lo, lo + name.len()) })
+    }
+
+    fn parse_expr(script: &str) -> Expr {
+        let sess = ParserSession::from_string(script, Game::TESV);
+        let expr = Parser::from_sess(&sess).parse_expr().unwrap();
+        expr
+    }
+
+    #[test]
+    fn expr_arithmetic() {
+        assert_eq!(
+            Expr::Binary {
+                op: BinOp::Add,
+                lhs: ident("a", 0),
+                rhs: Box::new(Expr::Binary {
+                    op: BinOp::Mul,
+                    lhs: ident("b", 4),
+                    rhs: ident("c", 8),
+                    span: (4, 9),
+                }),
+                span: (0, 9),
+            },
+            parse_expr("a + b * c")
+        );
+        assert_eq!(
+            Expr::Binary {
+                op: BinOp::Mul,
+                lhs: Box::new(Expr::Paren {
+                    expr: Box::new(Expr::Binary {
+                        op: BinOp::Sub,
+                        lhs: ident("a", 1),
+                        rhs: ident("b", 5),
+                        span: (1, 6),
+                    }),
+                    span: (0, 7),
+                }),
+                rhs: ident("c", 10),
+                span: (0, 11),
+            },
+            parse_expr("(a - b) * c")
+        );
+    }
+
+    #[test]
+    fn expr_minus() {
+        let int = |value, lo| {
+            Box::new(Expr::Literal { lit: LitKind::Integer(value, false), span: (lo, lo + 1) })
+        };
+        assert_eq!(
+            Expr::Binary { op: BinOp::Sub, lhs: int(3, 0), rhs: int(5, 2), span: (0, 3) },
+            parse_expr("3-5")
+        );
+        assert_eq!(
+            Expr::Binary { op: BinOp::Sub, lhs: int(3, 0), rhs: int(5, 4), span: (0, 5) },
+            parse_expr("3 - 5")
+        );
+        assert_eq!(Expr::Unary { op: UnOp::Neg, expr: int(5, 1), span: (0, 2) }, parse_expr("-5"));
+    }
+
+    #[test]
+    fn expr_logical() {
+        assert_eq!(
+            Expr::Binary {
+                op: BinOp::Or,
+                lhs: Box::new(Expr::Binary {
+                    op: BinOp::And,
+                    lhs: Box::new(Expr::Unary { op: UnOp::Not, expr: ident("x", 1), span: (0, 2) }),
+                    rhs: ident("y", 6),
+                    span: (0, 7),
+                }),
+                rhs: ident("z", 11),
+                span: (0, 12),
+            },
+            parse_expr("!x && y || z")
+        );
+    }
+
+    #[test]
+    fn expr_comparison() {
+        assert_eq!(
+            Expr::Binary {
+                op: BinOp::Eq,
+                lhs: ident("a", 0),
+                rhs: Box::new(Expr::Binary {
+                    op: BinOp::Add,
+                    lhs: ident("b", 5),
+                    rhs: Box::new(Expr::Literal { lit: LitKind::Integer(1, false), span: (9, 10) }),
+                    span: (5, 10),
+                }),
+                span: (0, 10),
+            },
+            parse_expr("a == b + 1")
+        );
+    }
+
+    #[test]
+    fn expr_comparison_chained() {
+        let sess = ParserSession::from_string("a < b < c", Game::TESV);
+        let expr = Parser::from_sess(&sess).parse_expr().unwrap();
+        assert!(matches!(expr, Expr::Binary { op: BinOp::Lt, .. }));
+        let diagnostics = sess.diagnostics();
+        assert_eq!(1, diagnostics.len());
+        assert_eq!("comparison operators cannot be chained", diagnostics[0].title);
+        assert_eq!((0, 9), diagnostics[0].span);
+
+        let sess = ParserSession::from_string("(a < b) == c && b < c", Game::TESV);
+        Parser::from_sess(&sess).parse_expr().unwrap();
+        assert!(sess.diagnostics().is_empty());
+    }
+
+    #[test]
+    fn expr_bool() {
+        assert_eq!(
+            Expr::Unary {
+                op: UnOp::Not,
+                expr: Box::new(Expr::Literal { lit: LitKind::Bool(true), span: (1, 5) }),
+                span: (0, 5),
+            },
+            parse_expr("!True")
+        );
+    }
+
+    #[test]
+    fn expr_none() {
+        assert_eq!(
+            Expr::Binary {
+                op: BinOp::Eq,
+                lhs: ident("x", 0),
+                rhs: Box::new(Expr::Literal { lit: LitKind::None, span: (5, 9) }),
+                span: (0, 9),
+            },
+            parse_expr("x == None")
+        );
+    }
+
+    #[test]
+    fn expr_self_parent() {
+        assert_eq!(Expr::SelfRef { span: (0, 4) }, parse_expr("self"));
+        assert_eq!(
+            Expr::Member {
+                base: Box::new(Expr::SelfRef { span: (0, 4) }),
+                field: "x".to_string(),
+                span: (0, 6),
+            },
+            parse_expr("self.x")
+        );
+        assert_eq!(
+            Expr::Call {
+                callee: Box::new(Expr::Member {
+                    base: Box::new(Expr::ParentRef { span: (0, 6) }),
+                    field: "Foo".to_string(),
+                    span: (0, 10),
+                }),
+                args: vec![],
+                span: (0, 12),
+            },
+            parse_expr("Parent.Foo()")
+        );
+    }
+
+    #[test]
+    fn expr_postfix() {
+        assert_eq!(
+            Expr::Unary {
+                op: UnOp::Neg,
+                expr: Box::new(Expr::Index {
+                    base: Box::new(Expr::Call {
+                        callee: Box::new(Expr::Member {
+                            base: ident("a", 1),
+                            field: "b".to_string(),
+                            span: (1, 4),
+                        }),
+                        args: vec![
+                            Arg { name: None, value: *ident("c", 5), span: (5, 6) },
+                            Arg { name: None, value: *ident("d", 8), span: (8, 9) },
+                        ],
+                        span: (1, 10),
+                    }),
+                    index: Box::new(Expr::Literal {
+                        lit: LitKind::Integer(0, false),
+                        span: (11, 12)
+                    }),
+                    span: (1, 13),
+                }),
+                span: (0, 13),
+            },
+            parse_expr("-a.b(c, d)[0]")
+        );
+    }
+
+    #[test]
+    fn expr_member() {
+        let member = |base, field: &str, span| {
+            Box::new(Expr::Member { base, field: field.to_string(), span })
+        };
+        assert_eq!(*member(ident("a", 0), "b", (0, 3)), parse_expr("a.b"));
+        assert_eq!(*member(member(ident("a", 0), "b", (0, 3)), "c", (0, 5)), parse_expr("a.b.c"));
+        let call = Box::new(Expr::Call {
+            callee: member(ident("a", 0), "b", (0, 3)),
+            args: vec![],
+            span: (0, 5),
+        });
+        assert_eq!(*member(call, "c", (0, 7)), parse_expr("a.b().c"));
+        // struct fields and group properties are told apart later, FO4 parses them the same way
+        let sess = ParserSession::from_string("Self.b", Game::FO4);
+        assert!(matches!(
+            Parser::from_sess(&sess).parse_expr(),
+            Ok(Expr::Member { base, .. }) if matches!(*base, Expr::SelfRef { .. })
+        ));
+    }
+
+    #[test]
+    fn expr_call_named() {
+        let sess = ParserSession::from_string("Foo(x = 1, y)", Game::FO4);
+        match Parser::from_sess(&sess).parse_expr() {
+            Ok(Expr::Call { args, span, .. }) => {
+                assert_eq!(Some("x".to_string()), args[0].name);
+                assert_eq!((4, 9), args[0].span);
+                assert_eq!(Arg { name: None, value: *ident("y", 11), span: (11, 12) }, args[1]);
+                assert_eq!((0, 13), span);
+            }
+            expr => panic!("expected call, found {:?}", expr),
+        }
+        assert!(sess.diagnostics().is_empty());
+    }
+
+    #[test]
+    fn expr_call_named_wrong_game() {
+        let sess = ParserSession::from_string("Foo(x = 1)", Game::TESV);
+        assert!(Parser::from_sess(&sess).parse_expr().is_ok());
+        let diagnostics = sess.diagnostics();
+        assert_eq!("named arguments are only available in Fallout 4", diagnostics[0].title);
+        assert_eq!((4, 5), diagnostics[0].span);
+    }
+
+    #[test]
+    fn expr_call_errors() {
+        let sess = ParserSession::from_string("Foo(a,)", Game::TESV);
+        assert!(Parser::from_sess(&sess).parse_expr().is_ok());
+        assert_eq!((5, 6), sess.diagnostics()[0].span);
+
+        let sess = ParserSession::from_string("Foo(a, b\nc", Game::TESV);
+        assert_eq!(Err(ParseError), Parser::from_sess(&sess).parse_expr());
+        let diagnostics = sess.diagnostics();
+        assert_eq!("expected ')' to close argument list", diagnostics[0].title);
+        assert_eq!((3, 4), diagnostics[0].span);
+    }
+
+    #[test]
+    fn expr_stops_at_newline() {
+        let sess = ParserSession::from_string("a + b\n* c", Game::TESV);
+        let mut parser = Parser::from_sess(&sess);
+        assert_eq!((0, 5), parser.parse_expr().unwrap().span());
+        assert_eq!(TokenKind::Multiply, parser.token.kind);
+    }
+
+    #[test]
+    fn expr_cast() {
+        assert_eq!(
+            Expr::Cast { expr: ident("x", 0), ty: Type::Int, span: (0, 8) },
+            parse_expr("x as Int")
+        );
+        assert_eq!(
+            Expr::Cast {
+                expr: Box::new(Expr::Member {
+                    base: ident("a", 0),
+                    field: "b".to_string(),
+                    span: (0, 3)
+                }),
+                ty: Type::Object("Actor".to_string()),
+                span: (0, 12),
+            },
+            parse_expr("a.b as Actor")
+        );
+        assert_eq!(
+            Expr::Cast { expr: ident("x", 0), ty: Type::Array(Box::new(Type::Int)), span: (0, 10) },
+            parse_expr("x as Int[]")
+        );
+    }
+
+    #[test]
+    fn expr_type_check() {
+        let sess = ParserSession::from_string("x Is Actor && (a as Foo) Is Bar", Game::FO4);
+        assert_eq!(
+            Ok(Expr::Binary {
+                op: BinOp::And,
+                lhs: Box::new(Expr::TypeCheck {
+                    expr: ident("x", 0),
+                    ty: Type::Object("Actor".to_string()),
+                    span: (0, 10),
+                }),
+                rhs: Box::new(Expr::TypeCheck {
+                    expr: Box::new(Expr::Paren {
+                        expr: Box::new(Expr::Cast {
+                            expr: ident("a", 15),
+                            ty: Type::Object("Foo".to_string()),
+                            span: (15, 23),
+                        }),
+                        span: (14, 24),
+                    }),
+                    ty: Type::Object("Bar".to_string()),
+                    span: (14, 31),
+                }),
+                span: (0, 31),
+            }),
+            Parser::from_sess(&sess).parse_expr()
+        );
+    }
+
+    #[test]
+    fn expr_type_check_wrong_game() {
+        let sess = ParserSession::from_string("x Is Actor", Game::TESV);
+        assert_eq!(Err(ParseError), Parser::from_sess(&sess).parse_expr());
+        let diagnostics = sess.diagnostics();
+        assert_eq!("type checks are only available in Fallout 4", diagnostics[0].title);
+        assert_eq!((2, 4), diagnostics[0].span);
+    }
+
+    #[test]
+    fn expr_cast_chain() {
+        assert_eq!(
+            Expr::Cast {
+                expr: Box::new(Expr::Cast {
+                    expr: ident("a", 0),
+                    ty: Type::Object("Foo".to_string()),
+                    span: (0, 8),
+                }),
+                ty: Type::Object("Bar".to_string()),
+                span: (0, 15),
+            },
+            parse_expr("a as Foo as Bar")
+        );
+        assert_eq!(
+            Expr::Unary {
+                op: UnOp::Not,
+                expr: Box::new(Expr::Cast { expr: ident("a", 1), ty: Type::Bool, span: (1, 10) }),
+                span: (0, 10),
+            },
+            parse_expr("!a as Bool")
+        );
+    }
+
+    #[test]
+    fn expr_new_array() {
+        assert_eq!(
+            Expr::NewArray {
+                ty: Type::Int,
+                size: Box::new(Expr::Literal { lit: LitKind::Integer(10, false), span: (8, 10) }),
+                span: (0, 11),
+            },
+            parse_expr("New Int[10]")
+        );
+        assert_eq!(
+            Expr::NewArray {
+                ty: Type::Object("Foo".to_string()),
+                size: ident("count", 8),
+                span: (0, 14),
+            },
+            parse_expr("New Foo[count]")
+        );
+    }
+
+    #[test]
+    fn expr_new_array_missing_size() {
+        let sess = ParserSession::from_string("New Int[]", Game::TESV);
+        assert_eq!(Err(ParseError), Parser::from_sess(&sess).parse_expr());
+        let diagnostics = sess.diagnostics();
+        assert_eq!("expected array size", diagnostics[0].title);
+        assert_eq!((8, 9), diagnostics[0].span);
+    }
+
+    #[test]
+    fn expr_new_struct() {
+        let sess = ParserSession::from_string("New Point", Game::FO4);
+        assert_eq!(
+            Ok(Expr::NewStruct { name: "Point".to_string(), span: (0, 9) }),
+            Parser::from_sess(&sess).parse_expr()
+        );
+        let sess = ParserSession::from_string("New Point", Game::TESV);
+        assert_eq!(Err(ParseError), Parser::from_sess(&sess).parse_expr());
+    }
+
+    #[test]
+    fn expr_dangling_operator() {
+        let sess = ParserSession::from_string("a +\nb", Game::TESV);
+        assert_eq!(Err(ParseError), Parser::from_sess(&sess).parse_expr());
+        assert_eq!((2, 3), sess.diagnostics()[0].span);
+    }
+
+    fn parse_stmt(script: &str) -> Stmt {
+        let sess = ParserSession::from_string(script, Game::TESV);
+        let stmt = Parser::from_sess(&sess).parse_stmt().unwrap();
+        stmt
+    }
+
+    fn parse_var_decl(script: &str, game: Game) -> VarDecl {
+        let sess = ParserSession::from_string(script, game);
+        let decl = Parser::from_sess(&sess).parse_var_decl().unwrap();
+        decl
+    }
+
+    fn parse_type(script: &str) -> Type {
+        let sess = ParserSession::from_string(script, Game::FO4);
+        let ty = Parser::from_sess(&sess).parse_type().unwrap();
+        assert!(sess.diagnostics().is_empty());
+        ty
+    }
+
+    #[test]
+    fn types() {
+        assert_eq!(Type::Int, parse_type("Int"));
+        assert_eq!(Type::Var, parse_type("Var"));
+        assert_eq!(Type::Object("Foo".to_string()), parse_type("Foo"));
+        assert_eq!(Type::Array(Box::new(Type::Int)), parse_type("Int[]"));
+    }
+
+    #[test]
+    fn type_nested_array() {
+        let sess = ParserSession::from_string("Int[][] x", Game::TESV);
+        let mut parser = Parser::from_sess(&sess);
+        assert_eq!(Ok(Type::Array(Box::new(Type::Int))), parser.parse_type());
+        assert_eq!(TokenKind::Ident("x".to_string()), parser.token.kind);
+        let diagnostics = sess.diagnostics();
+        assert_eq!("arrays of arrays are not supported", diagnostics[0].title);
+        assert_eq!((5, 7), diagnostics[0].span);
+    }
+
+    #[test]
+    fn var_decl() {
+        assert_eq!(
+            VarDecl {
+                ty: Type::Int,
+                name: "x".to_string(),
+                init: None,
+                flags: vec![],
+                span: (0, 5)
+            },
+            parse_var_decl("Int x", Game::TESV)
+        );
+        assert_eq!(
+            Some(Expr::Literal { lit: LitKind::Integer(5, false), span: (8, 9) }),
+            parse_var_decl("Int x = 5", Game::TESV).init
+        );
+        assert_eq!(
+            Type::Array(Box::new(Type::Float)),
+            parse_var_decl("Float[] arr", Game::TESV).ty
+        );
+    }
+
+    #[test]
+    fn var_decl_flags() {
+        let decl = parse_var_decl("Int foo = 1 Const", Game::FO4);
+        assert_eq!(vec![VarFlag::Const], decl.flags);
+        assert_eq!((0, 17), decl.span);
+    }
+
+    #[test]
+    fn var_decl_misplaced_flag() {
+        let sess = ParserSession::from_string("Int foo DebugOnly Const", Game::FO4);
+        let decl = Parser::from_sess(&sess).parse_var_decl().unwrap();
+        assert_eq!(vec![VarFlag::Const], decl.flags);
+        let diagnostics = sess.diagnostics();
+        assert_eq!("'DebugOnly' is not a valid variable flag", diagnostics[0].title);
+        assert_eq!((8, 17), diagnostics[0].span);
+    }
+
+    #[test]
+    fn var_decl_flags_wrong_game() {
+        let sess = ParserSession::from_string("Int foo = 1 Const", Game::TESV);
+        assert_eq!(Err(ParseError), Parser::from_sess(&sess).parse_var_decl());
+        assert_eq!("const variables are only available in Fallout 4", sess.diagnostics()[0].title);
+    }
+
+    #[test]
+    fn var_decl_stmt() {
+        assert!(matches!(parse_stmt("Actor target\n"), Stmt::Var(_)));
+        assert!(matches!(parse_stmt("Actor[] targets"), Stmt::Var(_)));
+        assert!(matches!(parse_stmt("targets[0]"), Stmt::Expr(Expr::Index { .. })));
+        assert!(matches!(parse_stmt("Actor\ntarget"), Stmt::Expr(Expr::Ident { .. })));
+    }
+
+    #[test]
+    fn assign() {
+        assert_eq!(
+            Stmt::Assign {
+                target: *ident("x", 0),
+                op: AssignOp::Assign,
+                value: Expr::Literal { lit: LitKind::Integer(1, false), span: (4, 5) },
+                span: (0, 5),
+            },
+            parse_stmt("x = 1")
+        );
+        match parse_stmt("arr[0] += 2") {
+            Stmt::Assign { target: Expr::Index { .. }, op: AssignOp::Add, span, .. } => {
+                assert_eq!((0, 11), span)
+            }
+            stmt => panic!("expected index assignment, found {:?}", stmt),
+        }
+        match parse_stmt("obj.field *= 3") {
+            Stmt::Assign { target: Expr::Member { .. }, op: AssignOp::Mul, .. } => (),
+            stmt => panic!("expected member assignment, found {:?}", stmt),
+        }
+    }
+
+    #[test]
+    fn assign_invalid_target() {
+        let sess = ParserSession::from_string("5 = x", Game::TESV);
+        assert!(Parser::from_sess(&sess).parse_stmt().is_ok());
+        let diagnostics = sess.diagnostics();
+        assert_eq!("invalid assignment target", diagnostics[0].title);
+        assert_eq!((0, 1), diagnostics[0].span);
+    }
+
+    #[test]
+    fn return_value() {
+        assert_eq!(Stmt::Return { value: None, span: (0, 6) }, parse_stmt("Return\nx"));
+        assert_eq!(
+            Stmt::Return {
+                value: Some(Expr::Literal { lit: LitKind::Integer(0, false), span: (7, 8) }),
+                span: (0, 8),
+            },
+            parse_stmt("Return 0")
+        );
+        assert_eq!(
+            Stmt::Return {
+                value: Some(Expr::Binary {
+                    op: BinOp::Add,
+                    lhs: ident("a", 7),
+                    rhs: ident("b", 11),
+                    span: (7, 12),
+                }),
+                span: (0, 12),
+            },
+            parse_stmt("Return a + b")
+        );
+    }
+
+    #[test]
+    fn if_plain() {
+        assert_eq!(
+            Stmt::If {
+                arms: vec![IfArm {
+                    cond: *ident("a", 3),
+                    body: vec![Stmt::Expr(Expr::Call {
+                        callee: ident("Foo", 6),
+                        args: vec![],
+                        span: (6, 11),
+                    })],
+                    span: (0, 11),
+                }],
+                else_block: None,
+                span: (0, 17),
+            },
+            parse_stmt("If a\n\tFoo()\nEndIf")
+        );
+    }
+
+    #[test]
+    fn if_else() {
+        assert_eq!(
+            Stmt::If {
+                arms: vec![IfArm { cond: *ident("a", 3), body: vec![], span: (0, 4) }],
+                else_block: Some(vec![Stmt::Expr(*ident("b", 10))]),
+                span: (0, 17),
+            },
+            parse_stmt("If a\nElse\nb\nEndIf")
+        );
+    }
+
+    #[test]
+    fn if_elseif_chain() {
+        let stmt = parse_stmt(
+            "If a\n\
+             \tIf b\n\
+             \tEndIf\n\
+             ElseIf c\n\
+             ElseIf d\n\
+             \tFoo()\n\
+             Else\n\
+             EndIf",
+        );
+        match stmt {
+            Stmt::If { arms, else_block, span } => {
+                assert_eq!(3, arms.len());
+                assert!(matches!(arms[0].body[0], Stmt::If { .. }));
+                assert_eq!((18, 26), arms[1].span);
+                assert_eq!(*ident("d", 34), arms[2].cond);
+                assert_eq!((27, 42), arms[2].span);
+                assert_eq!(Some(vec![]), else_block);
+                assert_eq!((0, 53), span);
+            }
+            _ => panic!("expected if statement"),
+        }
+    }
+
+    #[test]
+    fn if_assignment() {
+        let sess = ParserSession::from_string("If x = 5\nEndIf", Game::TESV);
+        let stmt = Parser::from_sess(&sess).parse_if().unwrap();
+        let diagnostics = sess.diagnostics();
+        assert_eq!(1, diagnostics.len());
+        assert_eq!("expected a condition, found an assignment", diagnostics[0].title);
+        assert_eq!(Some("use '==' to compare values".to_string()), diagnostics[0].label);
+        assert_eq!((5, 6), diagnostics[0].span);
+        match stmt {
+            Stmt::If { arms, .. } => {
+                assert!(matches!(arms[0].cond, Expr::Binary { op: BinOp::Eq, .. }))
+            }
+            _ => panic!("expected if statement"),
+        }
+
+        let sess = ParserSession::from_string("While x = 5\nEndWhile", Game::TESV);
+        Parser::from_sess(&sess).parse_while().unwrap();
+        assert_eq!("expected a condition, found an assignment", sess.diagnostics()[0].title);
+    }
+
+    #[test]
+    fn if_unterminated() {
+        let sess = ParserSession::from_string("If a\nFoo()", Game::TESV);
+        assert_eq!(Err(ParseError), Parser::from_sess(&sess).parse_if());
+        let diagnostics = sess.diagnostics();
+        assert_eq!(1, diagnostics.len());
+        assert_eq!("expected 'EndIf' before end of file", diagnostics[0].title);
+        assert_eq!((0, 2), diagnostics[0].span);
+    }
+
+    #[test]
+    fn lexer_fatal_is_not_repeated() {
+        let sess = ParserSession::from_string("If a\nFoo(\"abc)", Game::TESV);
+        assert_eq!(Err(ParseError), Parser::from_sess(&sess).parse_if());
+        assert_eq!(1, sess.diagnostics().len());
+        assert!(sess.has_fatal());
+    }
+
+    #[test]
+    fn while_empty() {
+        assert_eq!(
+            Stmt::While { cond: *ident("a", 6), body: vec![], span: (0, 16) },
+            parse_stmt("While a\nEndWhile")
+        );
+    }
+
+    #[test]
+    fn while_body() {
+        assert_eq!(
+            Stmt::While {
+                cond: *ident("a", 6),
+                body: vec![Stmt::Expr(*ident("b", 9)), Stmt::Expr(*ident("c", 12))],
+                span: (0, 22),
+            },
+            parse_stmt("While a\n\tb\n\tc\nEndWhile")
+        );
+    }
+
+    #[test]
+    fn while_nested() {
+        let stmt = parse_stmt(
+            "While a\n\
+             \tWhile b\n\
+             \t\tIf c\n\
+             \t\tEndIf\n\
+             \tEndWhile\n\
+             EndWhile",
+        );
+        match stmt {
+            Stmt::While { body, .. } => match &body[0] {
+                Stmt::While { cond, body, span } => {
+                    assert_eq!(*ident("b", 15), *cond);
+                    assert!(matches!(body[0], Stmt::If { .. }));
+                    assert_eq!((9, 41), *span);
+                }
+                _ => panic!("expected nested while statement"),
+            },
+            _ => panic!("expected while statement"),
+        }
+    }
+
+    #[test]
+    fn while_unterminated() {
+        let sess = ParserSession::from_string("While a\nFoo()", Game::TESV);
+        assert_eq!(Err(ParseError), Parser::from_sess(&sess).parse_while());
+        assert_eq!((0, 5), sess.diagnostics()[0].span);
+    }
+
+    #[test]
+    fn unknown_token_reported_once() {
+        let sess = ParserSession::from_string("a + $", Game::TESV);
+        assert_eq!(Err(ParseError), Parser::from_sess(&sess).parse_expr());
+        assert_eq!(1, sess.diagnostics().len());
+    }
+
+    #[test]
+    fn header_flags() {
+        let sess = ParserSession::from_string(
+            "ScriptName Foo Extends Bar Native Hidden Conditional",
+            Game::TESV,
+        );
+        let header = Parser::from_sess(&sess).parse_header().unwrap();
+        assert_eq!(
+            ScriptHeader {
+                name: "Foo".to_string(),
+                extends: Some("Bar".to_string()),
+                flags: vec![ScriptFlag::Native, ScriptFlag::Hidden, ScriptFlag::Conditional],
+                doc: None,
+                span: (0, 52),
+            },
+            header
+        );
+    }
+
+    #[test]
+    fn header_fo4_flags() {
+        let script = "ScriptName Foo extends Quest Const BetaOnly DebugOnly Default
+                      Actor Property Target Auto Const Mandatory
+";
+        let sess = ParserSession::from_string(script, Game::FO4);
+        let script = Parser::from_sess(&sess).parse_script().unwrap();
+        assert!(sess.diagnostics().is_empty());
+        assert_eq!(
+            vec![
+                ScriptFlag::Const,
+                ScriptFlag::BetaOnly,
+                ScriptFlag::DebugOnly,
+                ScriptFlag::Default
+            ],
+            script.header.flags
+        );
+        match &script.members[..] {
+            [Member::Property(property)] => {
+                assert_eq!(vec![PropertyFlag::Const, PropertyFlag::Mandatory], property.flags)
+            }
+            members => panic!("expected a property, found {:?}", members),
+        }
+    }
+
+    #[test]
+    fn header_fo4_flags_wrong_game() {
+        let script = "ScriptName Foo extends Quest Const Hidden
+                      Actor Property Target Auto Mandatory
+                      Int x
+";
+        let sess = ParserSession::from_string(script, Game::TESV);
+        let script = Parser::from_sess(&sess).parse_script().unwrap();
+        let diagnostics = sess.diagnostics();
+        assert_eq!(2, diagnostics.len());
+        assert_eq!("const scripts are only available in Fallout 4", diagnostics[0].title);
+        assert_eq!((29, 34), diagnostics[0].span);
+        assert_eq!("mandatory properties are only available in Fallout 4", diagnostics[1].title);
+        assert_eq!(vec![ScriptFlag::Hidden], script.header.flags);
+        assert!(matches!(script.members[..], [Member::Var(_)]));
+    }
+}
@@ -0,0 +1,187 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::lexer::{KwKind, LitKind, Token, TokenKind};
+
+/// Highlighting category of a token, modelled after LSP semantic token types.
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum SemanticKind {
+    Keyword,
+    Type,
+    Function,
+    Property,
+    Variable,
+    Comment,
+    String,
+    Number,
+    Operator,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SemanticToken {
+    pub kind: SemanticKind,
+    pub span: (usize, usize),
+}
+
+fn is_trivia(kind: &TokenKind) -> bool {
+    matches!(
+        kind,
+        TokenKind::Whitespace
+            | TokenKind::Newline(_)
+            | TokenKind::LineContinuation
+            | TokenKind::Comment(_)
+    )
+}
+
+// what may follow the type of a declaration: its name, or `Property` or `Function`
+fn is_declared_name(kind: &TokenKind) -> bool {
+    matches!(
+        kind,
+        TokenKind::Ident(_)
+            | TokenKind::Keyword(KwKind::Property)
+            | TokenKind::Keyword(KwKind::Function)
+    )
+}
+
+/// Classifies `tokens`, skipping whitespace, newlines, punctuation and unknown tokens.
+///
+/// Identifiers are only refined from their neighbours: the name after `Function` or `Event` and
+/// any callee are functions, the name after `Property` is a property, a name followed by another
+/// name, `Property` or `Function`, optionally through `[]`, or following `As`, `Is` or `New` is a
+/// type and the rest are variables.
+pub(crate) fn classify(tokens: &[Token]) -> Vec<SemanticToken> {
+    let mut semantic = vec![];
+    let mut prev: Option<&TokenKind> = None;
+    for (i, token) in tokens.iter().enumerate() {
+        let kind = match &token.kind {
+            TokenKind::Comment(_) | TokenKind::Doc(_) => Some(SemanticKind::Comment),
+            TokenKind::Literal(LitKind::Bool(_)) | TokenKind::Literal(LitKind::None) => {
+                Some(SemanticKind::Keyword)
+            }
+            TokenKind::Literal(LitKind::Str(_)) => Some(SemanticKind::String),
+            TokenKind::Literal(_) => Some(SemanticKind::Number),
+            TokenKind::Keyword(kw) if kw.is_type() => Some(SemanticKind::Type),
+            TokenKind::Keyword(_) => Some(SemanticKind::Keyword),
+            TokenKind::Ident(_) => {
+                let mut next = tokens[i + 1..]
+                    .iter()
+                    .filter(|token| !is_trivia(&token.kind))
+                    .map(|token| &token.kind);
+                match prev {
+                    Some(TokenKind::Keyword(KwKind::Function))
+                    | Some(TokenKind::Keyword(KwKind::Event)) => Some(SemanticKind::Function),
+                    Some(TokenKind::Keyword(KwKind::Property)) => Some(SemanticKind::Property),
+                    Some(TokenKind::Keyword(KwKind::As))
+                    | Some(TokenKind::Keyword(KwKind::Is))
+                    | Some(TokenKind::Keyword(KwKind::New)) => Some(SemanticKind::Type),
+                    _ => match next.next() {
+                        Some(TokenKind::LParen) => Some(SemanticKind::Function),
+                        Some(kind) if is_declared_name(kind) => Some(SemanticKind::Type),
+                        Some(TokenKind::LSquare)
+                            if next.next() == Some(&TokenKind::RSquare)
+                                && next.next().is_some_and(is_declared_name) =>
+                        {
+                            Some(SemanticKind::Type)
+                        }
+                        _ => Some(SemanticKind::Variable),
+                    },
+                }
+            }
+            TokenKind::Minus
+            | TokenKind::MinusEq
+            | TokenKind::Plus
+            | TokenKind::PlusEq
+            | TokenKind::Equal
+            | TokenKind::Not
+            | TokenKind::Multiply
+            | TokenKind::MultiplyEq
+            | TokenKind::Divide
+            | TokenKind::DivideEq
+            | TokenKind::Modulo
+            | TokenKind::ModuleEq
+            | TokenKind::CmpEQ
+            | TokenKind::CmpNE
+            | TokenKind::CmpLT
+            | TokenKind::CmpLE
+            | TokenKind::CmpGT
+            | TokenKind::CmpGE
+            | TokenKind::And
+            | TokenKind::Or => Some(SemanticKind::Operator),
+            _ => None,
+        };
+        if let Some(kind) = kind {
+            semantic.push(SemanticToken { kind, span: token.span() });
+        }
+        if !is_trivia(&token.kind) {
+            prev = Some(&token.kind);
+        }
+    }
+    semantic
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Game, ParserSession};
+
+    #[test]
+    fn classify() {
+        let script = "Int Property Count Auto\n\
+                      Function Foo(Actor target) ; comment\n\
+                      \ttarget.Kill(\"now\", 1 + x)\n\
+                      EndFunction\n\
+                      Actor Property Target Auto\n\
+                      Actor[] Function Get()\n\
+                      \tActor[] all = New Actor[2]\n\
+                      \tReturn y As Actor\n\
+                      EndFunction";
+        let sess = ParserSession::from_string(script, Game::TESV);
+        let kinds = sess
+            .semantic_tokens()
+            .into_iter()
+            .map(|token| (&script[token.span.0..token.span.1], token.kind))
+            .collect::<Vec<_>>();
+        use SemanticKind::*;
+        assert_eq!(
+            vec![
+                ("Int", Type),
+                ("Property", Keyword),
+                ("Count", Property),
+                ("Auto", Keyword),
+                ("Function", Keyword),
+                ("Foo", Function),
+                ("Actor", Type),
+                ("target", Variable),
+                ("; comment", Comment),
+                ("target", Variable),
+                ("Kill", Function),
+                ("\"now\"", String),
+                ("1", Number),
+                ("+", Operator),
+                ("x", Variable),
+                ("EndFunction", Keyword),
+                ("Actor", Type),
+                ("Property", Keyword),
+                ("Target", Property),
+                ("Auto", Keyword),
+                ("Actor", Type),
+                ("Function", Keyword),
+                ("Get", Function),
+                ("Actor", Type),
+                ("all", Variable),
+                ("=", Operator),
+                ("New", Keyword),
+                ("Actor", Type),
+                ("2", Number),
+                ("Return", Keyword),
+                ("y", Variable),
+                ("As", Keyword),
+                ("Actor", Type),
+                ("EndFunction", Keyword),
+            ],
+            kinds
+        );
+    }
+}